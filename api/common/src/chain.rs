@@ -0,0 +1,81 @@
+// Chain-agnostic view of a blockchain that mints NFTs. The event listener and the
+// backfill script only talk to chains through `ChainAdapter`; each chain family
+// (currently EVM, see `evm`) provides one implementation.
+
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+
+// One NFT log the indexer acts on.
+#[derive(Debug, Clone)]
+pub struct ChainEvent {
+    pub contract_address: String, // lowercase, 0x-prefixed
+    pub kind: EventKind,
+    pub block_number: u64,
+    pub block_hash: String,
+    pub log_index: u64,
+    pub tx_hash: String,
+    // Set when the node retracts a log it delivered earlier because its block was reorganized away.
+    pub removed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum EventKind {
+    // `amounts[i]` of `token_ids[i]` moved from `from` to `to`. `from` is None for a mint,
+    // `to` for a burn.
+    Transfer {
+        token_standard: &'static str, // 'erc721' or 'erc1155'
+        from: Option<String>,
+        to: Option<String>,
+        token_ids: Vec<String>, // decimal; several for an ERC-1155 batch transfer
+        amounts: Vec<String>,   // decimal; always 1 for ERC-721
+    },
+    // The metadata of every token in the inclusive id range changed (EIP-4906); both ends
+    // are the same token for a single-token update.
+    MetadataUpdate {
+        from_token_id: String,
+        to_token_id: String,
+    },
+    // An ERC-1155 token's metadata URI was set or changed to `uri`, which may hold the
+    // `{id}` placeholder.
+    UriChanged {
+        token_id: String,
+        uri: String,
+    },
+}
+
+// Contract-level getters; any of them may be missing on a given contract.
+#[derive(Debug, Clone, Default)]
+pub struct CollectionInfo {
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub total_supply: Option<String>,
+    pub contract_uri: Option<String>,
+}
+
+#[async_trait]
+pub trait ChainAdapter: Send + Sync {
+    // Name used in jobs, checkpoints and API routes, e.g. 'ethereum', 'polygon'.
+    fn chain(&self) -> &str;
+
+    async fn head(&self) -> anyhow::Result<u64>;
+
+    // Hash of the canonical block at `block_number`, or None if the node does not have it yet.
+    async fn block_hash(&self, block_number: u64) -> anyhow::Result<Option<String>>;
+
+    // Live events in inclusion order, including `removed` retractions.
+    async fn subscribe_events(&self) -> anyhow::Result<BoxStream<'_, ChainEvent>>;
+
+    // Historical events in the inclusive block range, in log order.
+    async fn events_in_range(&self, from_block: u64, to_block: u64) -> anyhow::Result<Vec<ChainEvent>>;
+
+    async fn token_uri(&self, contract_address: &str, token_id: &str, token_standard: &str) -> anyhow::Result<String>;
+
+    async fn collection_info(&self, contract_address: &str) -> CollectionInfo;
+
+    // Token ids currently in a collection.
+    async fn enumerate_collection(&self, contract_address: &str) -> anyhow::Result<Vec<String>>;
+
+    // Token ids of a collection within the inclusive id range of a metadata update, or None
+    // when the range is too wide to expand.
+    async fn tokens_in_range(&self, contract_address: &str, from_token_id: &str, to_token_id: &str) -> anyhow::Result<Option<Vec<String>>>;
+}
@@ -1,11 +1,77 @@
 use serde::{Serialize, Deserialize};
 
+#[cfg(feature = "adapter")]
+pub mod chain;
+#[cfg(feature = "evm")]
+pub mod evm;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NftMintJob {
     pub contract_address: String,
     pub token_id: String,
     pub chain: String,
     pub metadata_uri: Option<String>,
+    // Block containing the mint log; absent for backfilled tokens.
+    #[serde(default)]
+    pub block_number: Option<u64>,
+    #[serde(default)]
+    pub block_hash: Option<String>,
+}
+
+// Contract-level details read on-chain when a collection is first seen.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionJob {
+    pub contract_address: String,
+    pub chain: String,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub token_standard: Option<String>, // e.g. 'erc721', 'erc1155'
+    pub total_supply: Option<String>,
+    pub first_seen_block: Option<u64>,
+    pub contract_uri: Option<String>,
+}
+
+// Emitted when a block the listener already produced jobs from is reorganized away:
+// tokens minted in that block (identified by its hash) are no longer canonical.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetractionJob {
+    pub chain: String,
+    pub block_number: u64,
+    pub block_hash: String,
+}
+
+// Asks the worker to fetch a token's metadata again and record it if it changed. Without
+// `metadata_uri`, the worker reads the token URI from the contract, falling back to the
+// one stored when the token was indexed. With `to_token_id`, every indexed token from
+// `token_id` to it is refreshed that way (a metadata update too wide for the listener to
+// expand). Tokens the worker has not indexed are skipped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshJob {
+    pub chain: String,
+    pub contract_address: String,
+    pub token_id: String,
+    #[serde(default)]
+    pub metadata_uri: Option<String>,
+    #[serde(default)]
+    pub to_token_id: Option<String>,
+}
+
+// One ERC-721 / ERC-1155 transfer log, mints and burns included: `amounts[i]` of
+// `token_ids[i]` moved from `from_address` (None for a mint) to `to_address` (None for a
+// burn). The log position identifies it, so a redelivered job is recorded once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferJob {
+    pub chain: String,
+    pub contract_address: String,
+    pub token_standard: String,
+    pub from_address: Option<String>,
+    pub to_address: Option<String>,
+    pub token_ids: Vec<String>,
+    pub amounts: Vec<String>,
+    pub tx_hash: String,
+    pub block_number: u64,
+    pub block_hash: String,
+    pub log_index: u64,
 }
 
 pub fn add(left: u64, right: u64) -> u64 {
@@ -0,0 +1,2 @@
+-- Trait filtering: `attributes @> '[{"trait_type": ..., "value": ...}]'` containment queries.
+CREATE INDEX IF NOT EXISTS idx_nft_metadata_attributes ON nft_metadata USING GIN (attributes jsonb_path_ops);
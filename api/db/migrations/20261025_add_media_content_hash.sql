@@ -0,0 +1,7 @@
+-- Media objects are stored under the SHA-256 of their bytes, so identical files served
+-- from different URLs (or by different collections) are stored once
+ALTER TABLE nft_media ADD COLUMN IF NOT EXISTS content_hash TEXT;
+ALTER TABLE nft_media ADD COLUMN IF NOT EXISTS byte_size BIGINT;
+ALTER TABLE nft_media ADD COLUMN IF NOT EXISTS mime_type TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_nft_media_content_hash ON nft_media (content_hash);
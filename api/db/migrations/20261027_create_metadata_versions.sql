@@ -0,0 +1,31 @@
+-- Token metadata can change after mint (reveals, dynamic NFTs, fixed metadata), so the
+-- token URI is kept for refreshes and every distinct version is kept as history
+ALTER TABLE nft_metadata ADD COLUMN IF NOT EXISTS token_uri TEXT;
+ALTER TABLE nft_metadata ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE;
+
+CREATE TABLE IF NOT EXISTS metadata_versions (
+    id SERIAL PRIMARY KEY,
+    chain TEXT NOT NULL,
+    contract_address TEXT NOT NULL,
+    token_id TEXT NOT NULL,
+    token_uri TEXT,
+    raw_metadata JSONB NOT NULL,
+    recorded_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_metadata_versions_token ON metadata_versions (chain, contract_address, token_id, recorded_at DESC);
+
+-- Metadata indexed before this table existed is its tokens' first version
+INSERT INTO metadata_versions (chain, contract_address, token_id, raw_metadata, recorded_at)
+SELECT chain, contract_address, token_id, raw_metadata, created_at
+FROM nft_metadata
+WHERE NOT EXISTS (SELECT 1 FROM metadata_versions);
+
+-- Last refresh requested through the API per token, for rate limiting
+CREATE TABLE IF NOT EXISTS metadata_refresh_requests (
+    chain TEXT NOT NULL,
+    contract_address TEXT NOT NULL,
+    token_id TEXT NOT NULL,
+    requested_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (chain, contract_address, token_id)
+);
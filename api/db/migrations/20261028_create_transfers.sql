@@ -0,0 +1,35 @@
+-- Transfers Table: every ERC-721 / ERC-1155 transfer log, one row per token moved (an
+-- ERC-1155 batch has one row per id). from_address is NULL for mints, to_address for burns.
+CREATE TABLE IF NOT EXISTS transfers (
+    id BIGSERIAL PRIMARY KEY,
+    chain TEXT NOT NULL,
+    contract_address TEXT NOT NULL,
+    token_id TEXT NOT NULL,
+    from_address TEXT,
+    to_address TEXT,
+    amount NUMERIC(78, 0) NOT NULL,
+    tx_hash TEXT NOT NULL,
+    block_number BIGINT NOT NULL,
+    block_hash TEXT NOT NULL,
+    log_index BIGINT NOT NULL,
+    batch_index INTEGER NOT NULL, -- position of the token within the log
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (chain, block_hash, log_index, batch_index)
+);
+
+CREATE INDEX IF NOT EXISTS idx_transfers_token ON transfers (chain, contract_address, token_id, block_number DESC, log_index DESC, batch_index DESC);
+
+-- Token Balances Table: what each address holds, maintained from transfers (so it only
+-- covers transfers indexed since this table was added). An ERC-721 token's current owner
+-- is its one row with a positive balance.
+CREATE TABLE IF NOT EXISTS token_balances (
+    chain TEXT NOT NULL,
+    contract_address TEXT NOT NULL,
+    token_id TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    balance NUMERIC(78, 0) NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (chain, contract_address, token_id, owner)
+);
+
+CREATE INDEX IF NOT EXISTS idx_token_balances_owner ON token_balances (owner, chain, contract_address, token_id) WHERE balance > 0;
@@ -0,0 +1,12 @@
+-- Emitted Token URIs Table: the last URI an ERC-1155 `URI` event set for each token, written
+-- by the event listener. A mint in a later block whose `uri(id)` call fails uses it.
+CREATE TABLE IF NOT EXISTS emitted_token_uris (
+    chain TEXT NOT NULL,
+    contract_address TEXT NOT NULL,
+    token_id TEXT NOT NULL,
+    uri TEXT NOT NULL,
+    block_number BIGINT NOT NULL,
+    log_index BIGINT NOT NULL,
+    recorded_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (chain, contract_address, token_id)
+);
@@ -9,9 +9,17 @@
 // - attributes (jsonb)
 // - raw_metadata (jsonb)
 // - created_at (timestamp)
+// - search_vector (tsvector, generated) -- full-text index over name, description, attribute values
+// - block_number (bigint) -- block of the mint log, null for backfilled tokens
+// - block_hash (text)
+// - orphaned (boolean) -- set when the mint block was reorganized away
+// - token_uri (text) -- URI the metadata was fetched from, reused by refreshes
+// - updated_at (timestamp) -- last time the metadata changed
+// - burned (boolean) -- copied from token_supply.burned
 //
 // Table: nft_media
 // - id (serial primary key)
+// - chain (text)
 // - contract_address (text)
 // - token_id (text)
 // - media_type (text) -- e.g. 'image', 'animation'
@@ -19,6 +27,121 @@
 // - cached_url (text)
 // - storage_backend (text) -- e.g. 'local', 's3'
 // - created_at (timestamp)
+// - orphaned (boolean)
+// - content_hash (text) -- SHA-256 of the file, also its storage key
+// - byte_size (bigint)
+// - mime_type (text)
+//
+// Table: collections
+// - id (serial primary key)
+// - chain (text)
+// - contract_address (text)
+// - name (text)
+// - symbol (text)
+// - token_standard (text) -- e.g. 'erc721', 'erc1155'
+// - total_supply (text)
+// - first_seen_block (bigint)
+// - contract_uri (text)
+// - contract_metadata (jsonb)
+// - created_at (timestamp)
+// - updated_at (timestamp)
+//
+// Table: block_checkpoints (written by the event listener)
+// - chain (text primary key)
+// - last_block (bigint)
+// - updated_at (timestamp)
+//
+// Table: processed_blocks (written by the event listener)
+// - chain (text)
+// - block_number (bigint)
+// - block_hash (text)
+// - processed_at (timestamp)
+//
+// Table: failed_jobs (written by the metadata worker)
+// - id (serial primary key)
+// - topic (text) -- topic the job was originally published to
+// - job_key (text)
+// - payload (jsonb)
+// - attempts (integer)
+// - error_class (text) -- e.g. 'network', 'http_status', 'database'
+// - last_error (text)
+// - failed_at (timestamp)
+//
+// Table: metadata_versions -- every distinct metadata document a token has had
+// - id (serial primary key)
+// - chain (text)
+// - contract_address (text)
+// - token_id (text)
+// - token_uri (text)
+// - raw_metadata (jsonb)
+// - recorded_at (timestamp)
+//
+// Table: metadata_refresh_requests (written by the api, for rate limiting)
+// - chain (text)
+// - contract_address (text)
+// - token_id (text)
+// - requested_at (timestamp)
+//
+// Table: media_renditions (written by the metadata worker)
+// - id (serial primary key)
+// - content_hash (text) -- nft_media.content_hash of the original image
+// - width (integer) -- widths the original is no wider than point at the original itself
+// - height (integer)
+// - mime_type (text)
+// - cached_url (text)
+// - storage_backend (text)
+// - byte_size (bigint)
+// - created_at (timestamp)
+//
+// Table: transfers (written by the metadata worker) -- one row per token moved
+// - id (bigserial primary key)
+// - chain (text)
+// - contract_address (text)
+// - token_id (text)
+// - from_address (text) -- null for mints
+// - to_address (text) -- null for burns
+// - amount (numeric) -- always 1 for ERC-721
+// - tx_hash (text)
+// - block_number (bigint)
+// - block_hash (text)
+// - log_index (bigint)
+// - batch_index (integer) -- position of the token within an ERC-1155 batch
+// - created_at (timestamp)
+//
+// Table: token_balances (written by the metadata worker)
+// - chain (text)
+// - contract_address (text)
+// - token_id (text)
+// - owner (text)
+// - balance (numeric) -- current holders have a positive balance
+// - updated_at (timestamp)
+//
+// Table: token_supply (written by the metadata worker)
+// - chain (text)
+// - contract_address (text)
+// - token_id (text)
+// - token_standard (text)
+// - minted (numeric) -- amounts recorded in transfers
+// - burned_amount (numeric)
+// - last_mint (bigint[]) -- (block_number, log_index, batch_index) of the latest mint
+// - last_burn (bigint[])
+// - burned (boolean, generated) -- ERC-721: burned after the latest mint; ERC-1155: everything minted burned
+// - updated_at (timestamp)
+//
+// Table: retracted_blocks (written by the metadata worker)
+// - chain (text)
+// - block_hash (text) -- block reorganized away; rows from it stay orphaned
+// - block_number (bigint)
+// - retracted_at (timestamp)
+//
+// Table: emitted_token_uris (written by the event listener)
+// - chain (text)
+// - contract_address (text)
+// - token_id (text)
+// - uri (text) -- last URI an ERC-1155 `URI` event set for the token
+// - block_number (bigint)
+// - log_index (bigint)
+// - recorded_at (timestamp)
 
 use sqlx::{PgPool, FromRow}; // <--- ADDED FromRow here
 use serde::{Deserialize, Serialize}; // <--- ADDED Deserialize here (good practice if you're serializing/deserializing)
@@ -26,6 +149,7 @@ use serde_json::Value;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)] // <--- ADDED Debug, Clone, PartialEq, Deserialize, and FromRow derives
 pub struct NftMetadata {
+    pub id: i32,
     pub contract_address: String,
     pub token_id: String,
     pub chain: String,
@@ -35,10 +159,14 @@ pub struct NftMetadata {
     pub raw_metadata: Value,
     // IMPORTANT: Add the cached_image_url field as selected in your API worker's query
     pub cached_image_url: Option<String>, // <--- THIS IS THE NEW FIELD
+    // WebP renditions of the image by width, e.g. {"256": url, "512": url}
+    pub image_renditions: Option<Value>,
+    pub burned: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, FromRow)] // <--- Consider adding these for NftMedia too
 pub struct NftMedia {
+    pub chain: String,
     pub contract_address: String,
     pub token_id: String,
     pub media_type: String,
@@ -67,9 +195,10 @@ pub async fn insert_nft_metadata(pool: &PgPool, meta: &NftMetadata) -> Result<()
 
 pub async fn insert_nft_media(pool: &PgPool, media: &NftMedia) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        r#"INSERT INTO nft_media (contract_address, token_id, media_type, original_url, cached_url, storage_backend, created_at)
-           VALUES ($1, $2, $3, $4, $5, $6, NOW())
-           ON CONFLICT (contract_address, token_id, media_type) DO NOTHING"#,
+        r#"INSERT INTO nft_media (chain, contract_address, token_id, media_type, original_url, cached_url, storage_backend, created_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+           ON CONFLICT (chain, contract_address, token_id, media_type) DO NOTHING"#,
+        media.chain,
         media.contract_address,
         media.token_id,
         media.media_type,
@@ -0,0 +1,208 @@
+use axum::{extract::{Path, Query, State}, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+
+use crate::error::ApiError;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct ListCollectionsParams {
+    pub chain: Option<String>,
+    pub cursor: Option<i32>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollectionSummary {
+    pub id: i32,
+    pub chain: String,
+    pub contract_address: String,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub token_standard: Option<String>,
+    pub total_supply: Option<String>,
+    pub first_seen_block: Option<i64>,
+    pub contract_uri: Option<String>,
+    pub contract_metadata: Option<Value>,
+    pub token_count: i64, // unburned tokens indexed so far, not the on-chain supply
+    pub sample_image_url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollectionPage {
+    pub items: Vec<CollectionSummary>,
+    // Pass this back as `cursor` to fetch the next page; null once the list is exhausted.
+    pub next_cursor: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TraitsParams {
+    // Burned tokens are left out of the counts unless this is true.
+    #[serde(default)]
+    pub include_burned: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TraitValueCount {
+    pub value: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TraitFacet {
+    pub trait_type: String,
+    pub values: Vec<TraitValueCount>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollectionTraits {
+    pub chain: String,
+    pub contract_address: String,
+    pub traits: Vec<TraitFacet>,
+}
+
+#[axum::debug_handler]
+pub async fn list_collections(
+    State(pool): State<PgPool>,
+    Query(params): Query<ListCollectionsParams>,
+) -> Result<Json<CollectionPage>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let chain = params.chain.map(|c| c.to_lowercase()).filter(|c| !c.is_empty());
+
+    // Keyset pagination over c.id (newest first), same scheme as /nfts.
+    let mut collections = sqlx::query_as!(
+        CollectionSummary,
+        r#"
+        SELECT
+            c.id,
+            c.chain,
+            c.contract_address,
+            c.name,
+            c.symbol,
+            c.token_standard,
+            c.total_supply,
+            c.first_seen_block,
+            c.contract_uri,
+            c.contract_metadata,
+            (SELECT COUNT(*) FROM nft_metadata nm
+             WHERE nm.contract_address = c.contract_address AND nm.chain = c.chain AND NOT nm.orphaned AND NOT nm.burned) AS "token_count!",
+            (SELECT m.cached_url FROM nft_media m
+             WHERE m.contract_address = c.contract_address AND m.chain = c.chain AND m.media_type = 'image' AND NOT m.orphaned
+             ORDER BY m.id LIMIT 1) AS sample_image_url
+        FROM collections c
+        WHERE ($1::text IS NULL OR c.chain = $1)
+          AND ($2::int IS NULL OR c.id < $2)
+        ORDER BY c.id DESC
+        LIMIT $3
+        "#,
+        chain,
+        params.cursor,
+        limit + 1
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let next_cursor = if collections.len() as i64 > limit {
+        collections.truncate(limit as usize);
+        collections.last().map(|c| c.id)
+    } else {
+        None
+    };
+
+    Ok(Json(CollectionPage { items: collections, next_cursor }))
+}
+
+#[axum::debug_handler]
+pub async fn get_collection(
+    State(pool): State<PgPool>,
+    Path((chain, contract_address)): Path<(String, String)>,
+) -> Result<Json<CollectionSummary>, ApiError> {
+    let chain = chain.to_lowercase();
+    let contract_address = contract_address.to_lowercase();
+
+    let collection = sqlx::query_as!(
+        CollectionSummary,
+        r#"
+        SELECT
+            c.id,
+            c.chain,
+            c.contract_address,
+            c.name,
+            c.symbol,
+            c.token_standard,
+            c.total_supply,
+            c.first_seen_block,
+            c.contract_uri,
+            c.contract_metadata,
+            (SELECT COUNT(*) FROM nft_metadata nm
+             WHERE nm.contract_address = c.contract_address AND nm.chain = c.chain AND NOT nm.orphaned AND NOT nm.burned) AS "token_count!",
+            (SELECT m.cached_url FROM nft_media m
+             WHERE m.contract_address = c.contract_address AND m.chain = c.chain AND m.media_type = 'image' AND NOT m.orphaned
+             ORDER BY m.id LIMIT 1) AS sample_image_url
+        FROM collections c
+        WHERE c.chain = $1 AND c.contract_address = $2
+        "#,
+        chain,
+        contract_address
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| ApiError::NotFound(format!("Collection {}/{} not found", chain, contract_address)))?;
+
+    Ok(Json(collection))
+}
+
+#[axum::debug_handler]
+pub async fn list_traits(
+    State(pool): State<PgPool>,
+    Path((chain, contract_address)): Path<(String, String)>,
+    Query(params): Query<TraitsParams>,
+) -> Result<Json<CollectionTraits>, ApiError> {
+    let chain = chain.to_lowercase();
+    let contract_address = contract_address.to_lowercase();
+
+    // One row per (trait_type, value), ordered so rows for a trait type are contiguous.
+    // Tokens whose attributes are not an array of {trait_type, value} objects are skipped.
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            attr->>'trait_type' AS "trait_type!",
+            attr->>'value' AS "value!",
+            COUNT(*) AS "count!"
+        FROM
+            nft_metadata nm
+        CROSS JOIN LATERAL
+            jsonb_array_elements(
+                CASE WHEN jsonb_typeof(nm.attributes) = 'array' THEN nm.attributes ELSE '[]'::jsonb END
+            ) AS attr
+        WHERE nm.chain = $1
+          AND nm.contract_address = $2
+          AND NOT nm.orphaned
+          AND ($3 OR NOT nm.burned)
+          AND jsonb_typeof(attr) = 'object'
+          AND attr->>'trait_type' IS NOT NULL
+          AND attr->>'value' IS NOT NULL
+        GROUP BY 1, 2
+        ORDER BY 1, 3 DESC, 2
+        "#,
+        chain,
+        contract_address,
+        params.include_burned
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let mut traits: Vec<TraitFacet> = Vec::new();
+    for row in rows {
+        let value = TraitValueCount { value: row.value, count: row.count };
+        match traits.last_mut() {
+            Some(facet) if facet.trait_type == row.trait_type => facet.values.push(value),
+            _ => traits.push(TraitFacet { trait_type: row.trait_type, values: vec![value] }),
+        }
+    }
+
+    Ok(Json(CollectionTraits { chain, contract_address, traits }))
+}
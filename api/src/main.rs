@@ -1,44 +1,23 @@
-use axum::{routing::get, Router, Json, extract::State};
-use db::NftMetadata; // Assuming NftMetadata is in your 'db' crate and has 'cached_image_url' field
+mod collections;
+mod error;
+mod nfts;
+mod ownership;
+mod refresh;
+mod search;
+
+use axum::{extract::FromRef, routing::{get, post}, Router};
 use sqlx::PgPool;
 use std::net::SocketAddr;
 use std::env;
 use tower_http::cors::{CorsLayer, Any};
 use axum::http::{Method, HeaderValue};
+use refresh::RefreshQueue;
 
-#[axum::debug_handler]
-async fn list_nfts(State(pool): State<PgPool>) -> Json<Vec<NftMetadata>> {
-    let nfts = sqlx::query_as!(
-        // We select all existing columns from nft_metadata (nm.*)
-        // AND specifically the cached_url from the joined nft_media table, aliased as cached_image_url.
-        // NftMetadata struct MUST have a 'pub cached_image_url: Option<String>' field to match this.
-        NftMetadata,
-        r#"
-        SELECT
-            nm.contract_address,
-            nm.token_id,
-            nm.chain,
-            nm.name,
-            nm.description,
-            nm.attributes,
-            nm.raw_metadata,
-            img_media.cached_url AS cached_image_url -- Alias to match NftMetadata field
-        FROM
-            nft_metadata nm
-        LEFT JOIN
-            nft_media img_media ON nm.contract_address = img_media.contract_address
-                                AND nm.token_id = img_media.token_id
-                                AND img_media.media_type = 'image' -- Crucial: only get the image media
-        LIMIT 50
-        "#
-    )
-    .fetch_all(&pool)
-    .await
-    .unwrap_or_else(|e| {
-        eprintln!("Failed to fetch NFTs: {}", e); // Log the actual error for debugging
-        Vec::new() // Return an empty vector on error, so the API doesn't crash
-    });
-    Json(nfts)
+// Handlers extract only the part they need (`State<PgPool>`, `State<Option<RefreshQueue>>`).
+#[derive(Clone, FromRef)]
+pub struct AppState {
+    pool: PgPool,
+    refresh: Option<RefreshQueue>,
 }
 
 #[tokio::main]
@@ -49,6 +28,10 @@ async fn main() {
     // Database connection setup
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL environment variable must be set");
     let pool = PgPool::connect(&db_url).await.expect("Failed to connect to PostgreSQL database");
+    let refresh = RefreshQueue::from_env();
+    if refresh.is_none() {
+        println!("KAFKA_BROKERS not set, metadata refresh requests are disabled");
+    }
     
     // CORS (Cross-Origin Resource Sharing) configuration
     // This allows your frontend (nft-wikepedia-1.onrender.com) to make requests to this API.
@@ -67,10 +50,25 @@ async fn main() {
 
     // Create the Axum router
     let app = Router::new()
-        // Define the /nfts endpoint that handles GET requests
-        .route("/nfts", get(list_nfts))
-        // Share the database connection pool across all handlers
-        .with_state(pool.clone())
+        // Define the /nfts endpoint that handles GET requests (filterable, cursor-paginated)
+        .route("/nfts", get(nfts::list_nfts))
+        // Single token with all of its media rows; 404 with a JSON error body when unknown
+        .route("/nfts/:chain/:contract/:token_id", get(nfts::get_nft))
+        // Queue a metadata refresh for a token (rate limited per token)
+        .route("/nfts/:chain/:contract/:token_id/refresh", post(refresh::refresh_nft))
+        // Current holders and transfer history of a token, and the tokens an address holds
+        .route("/nfts/:chain/:contract/:token_id/owners", get(ownership::list_owners))
+        .route("/nfts/:chain/:contract/:token_id/history", get(ownership::list_history))
+        .route("/accounts/:address/nfts", get(ownership::list_account_nfts))
+        // Ranked full-text search with highlighted snippets
+        .route("/search", get(search::search_nfts))
+        // Trait facets (trait_type -> value counts) for the filter sidebar
+        .route("/collections/:chain/:address/traits", get(collections::list_traits))
+        // Collections with indexed token counts and a sample image
+        .route("/collections", get(collections::list_collections))
+        .route("/collections/:chain/:address", get(collections::get_collection))
+        // Share the database pool and the refresh queue across all handlers
+        .with_state(AppState { pool: pool.clone(), refresh })
         // Apply the CORS middleware to the router
         .layer(cors);
         
@@ -0,0 +1,294 @@
+use axum::{extract::{Path, State}, Json};
+// axum's own Query rejects repeated keys; the axum-extra one collects `trait=` into a Vec.
+use axum_extra::extract::Query;
+use db::{NftMedia, NftMetadata};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+
+use crate::error::ApiError;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+// Upper bound on the number of containment documents a set of trait filters expands to.
+const MAX_TRAIT_COMBINATIONS: usize = 256;
+
+// Query parameters accepted by GET /nfts. Every filter is optional; `cursor` is the
+// `next_cursor` value returned by the previous page.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListNftsParams {
+    pub chain: Option<String>,
+    pub contract_address: Option<String>,
+    pub token_id: Option<String>,
+    pub media_type: Option<String>, // e.g. 'image', 'animation'
+    pub q: Option<String>,          // free text, matched as a substring of name and description
+    // Repeatable `trait=type:value` filters: AND across trait types, OR within one type.
+    #[serde(rename = "trait", default)]
+    pub traits: Vec<String>,
+    // Burned tokens are left out unless this is true.
+    #[serde(default)]
+    pub include_burned: bool,
+    pub cursor: Option<i32>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NftPage {
+    pub items: Vec<NftMetadata>,
+    // Pass this back as `cursor` to fetch the next page; null once the index is exhausted.
+    pub next_cursor: Option<i32>,
+}
+
+// Response body for GET /nfts/:chain/:contract/:token_id: the token plus every cached media row.
+#[derive(Debug, Serialize)]
+pub struct NftDetail {
+    #[serde(flatten)]
+    pub metadata: NftMetadata,
+    pub media: Vec<NftMedia>,
+}
+
+// Escapes LIKE wildcards in user input and wraps it for a substring match.
+fn like_pattern(term: &str) -> String {
+    let escaped = term
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+// Treats empty query parameters (`?chain=`) the same as absent ones.
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+// Expands `type:value` filters into JSONB documents for `attributes @> ANY(...)`.
+//
+// Filters are grouped by trait type and the groups are multiplied out, so
+// `Hat:Cap`, `Hat:Crown`, `Eyes:Laser` becomes two documents (Cap+Laser, Crown+Laser):
+// a token matches if its attributes contain any one of them. Numeric values also
+// match attributes stored as JSON numbers. Keeping this a single containment test
+// lets Postgres answer it from the GIN index on `attributes`.
+fn trait_filter_documents(traits: &[String]) -> Result<Vec<Value>, ApiError> {
+    let mut groups: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+    for filter in traits {
+        let (trait_type, value) = filter
+            .split_once(':')
+            .map(|(t, v)| (t.trim(), v.trim()))
+            .filter(|(t, v)| !t.is_empty() && !v.is_empty())
+            .ok_or_else(|| ApiError::BadRequest(format!("Invalid trait filter '{}', expected type:value", filter)))?;
+        let alternatives = groups.entry(trait_type).or_default();
+        alternatives.push(json!({ "trait_type": trait_type, "value": value }));
+        if let Ok(number) = value.parse::<serde_json::Number>() {
+            alternatives.push(json!({ "trait_type": trait_type, "value": number }));
+        }
+    }
+
+    let mut documents: Vec<Vec<Value>> = vec![Vec::new()];
+    for alternatives in groups.values() {
+        if documents.len() * alternatives.len() > MAX_TRAIT_COMBINATIONS {
+            return Err(ApiError::BadRequest("Too many trait filter combinations".to_string()));
+        }
+        documents = documents
+            .iter()
+            .flat_map(|document| {
+                alternatives.iter().map(move |alternative| {
+                    let mut combined = document.clone();
+                    combined.push(alternative.clone());
+                    combined
+                })
+            })
+            .collect();
+    }
+    Ok(documents.into_iter().map(Value::Array).collect())
+}
+
+#[axum::debug_handler]
+pub async fn list_nfts(
+    State(pool): State<PgPool>,
+    Query(params): Query<ListNftsParams>,
+) -> Result<Json<NftPage>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let chain = non_empty(params.chain).map(|c| c.to_lowercase());
+    // Addresses are stored lowercase by both the event listener and the backfill script.
+    let contract_address = non_empty(params.contract_address).map(|a| a.to_lowercase());
+    let token_id = non_empty(params.token_id);
+    let media_type = non_empty(params.media_type).map(|m| m.to_lowercase());
+    let text_pattern = non_empty(params.q).map(|q| like_pattern(&q));
+    let trait_documents = if params.traits.is_empty() {
+        None
+    } else {
+        Some(trait_filter_documents(&params.traits)?)
+    };
+
+    // Keyset pagination over nm.id (newest first): fetch one extra row to know whether
+    // another page exists without running a separate COUNT query.
+    let mut nfts = sqlx::query_as!(
+        NftMetadata,
+        r#"
+        SELECT
+            nm.id,
+            nm.contract_address,
+            nm.token_id,
+            nm.chain,
+            nm.name,
+            nm.description,
+            nm.attributes,
+            nm.raw_metadata,
+            img_media.cached_url AS "cached_image_url?",
+            (SELECT jsonb_object_agg(r.width::text, r.cached_url)
+             FROM media_renditions r
+             WHERE r.content_hash = img_media.content_hash) AS "image_renditions?",
+            nm.burned
+        FROM
+            nft_metadata nm
+        LEFT JOIN
+            nft_media img_media ON nm.chain = img_media.chain
+                                AND nm.contract_address = img_media.contract_address
+                                AND nm.token_id = img_media.token_id
+                                AND img_media.media_type = 'image'
+        WHERE
+            NOT nm.orphaned
+            AND ($9 OR NOT nm.burned)
+            AND ($1::text IS NULL OR nm.chain = $1)
+            AND ($2::text IS NULL OR nm.contract_address = $2)
+            AND ($3::text IS NULL OR nm.token_id = $3)
+            AND ($4::text IS NULL OR EXISTS (
+                SELECT 1 FROM nft_media m
+                WHERE m.chain = nm.chain
+                  AND m.contract_address = nm.contract_address
+                  AND m.token_id = nm.token_id
+                  AND m.media_type = $4
+            ))
+            AND ($5::text IS NULL OR nm.name ILIKE $5 OR nm.description ILIKE $5)
+            AND ($6::jsonb[] IS NULL OR nm.attributes @> ANY($6))
+            AND ($7::int IS NULL OR nm.id < $7)
+        ORDER BY nm.id DESC
+        LIMIT $8
+        "#,
+        chain,
+        contract_address,
+        token_id,
+        media_type,
+        text_pattern,
+        trait_documents.as_deref(),
+        params.cursor,
+        limit + 1,
+        params.include_burned
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let next_cursor = if nfts.len() as i64 > limit {
+        nfts.truncate(limit as usize);
+        nfts.last().map(|nft| nft.id)
+    } else {
+        None
+    };
+
+    Ok(Json(NftPage { items: nfts, next_cursor }))
+}
+
+#[axum::debug_handler]
+pub async fn get_nft(
+    State(pool): State<PgPool>,
+    Path((chain, contract_address, token_id)): Path<(String, String, String)>,
+) -> Result<Json<NftDetail>, ApiError> {
+    let chain = chain.to_lowercase();
+    let contract_address = contract_address.to_lowercase();
+
+    let metadata = sqlx::query_as!(
+        NftMetadata,
+        r#"
+        SELECT
+            nm.id,
+            nm.contract_address,
+            nm.token_id,
+            nm.chain,
+            nm.name,
+            nm.description,
+            nm.attributes,
+            nm.raw_metadata,
+            img_media.cached_url AS "cached_image_url?",
+            (SELECT jsonb_object_agg(r.width::text, r.cached_url)
+             FROM media_renditions r
+             WHERE r.content_hash = img_media.content_hash) AS "image_renditions?",
+            nm.burned
+        FROM
+            nft_metadata nm
+        LEFT JOIN
+            nft_media img_media ON nm.chain = img_media.chain
+                                AND nm.contract_address = img_media.contract_address
+                                AND nm.token_id = img_media.token_id
+                                AND img_media.media_type = 'image'
+        WHERE nm.contract_address = $1 AND nm.token_id = $2 AND nm.chain = $3 AND NOT nm.orphaned
+        "#,
+        contract_address,
+        token_id,
+        chain
+    )
+    .fetch_optional(&pool)
+    .await?
+    .ok_or_else(|| {
+        ApiError::NotFound(format!("NFT {}/{}/{} not found", chain, contract_address, token_id))
+    })?;
+
+    let media = sqlx::query_as!(
+        NftMedia,
+        r#"
+        SELECT chain, contract_address, token_id, media_type, original_url, cached_url, storage_backend
+        FROM nft_media
+        WHERE chain = $1 AND contract_address = $2 AND token_id = $3
+        ORDER BY media_type
+        "#,
+        metadata.chain,
+        metadata.contract_address,
+        metadata.token_id
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(NftDetail { metadata, media }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trait_filters_and_across_types_or_within_type() {
+        let traits = vec!["Hat:Cap".to_string(), "Eyes:Laser".to_string(), "Hat:Crown".to_string()];
+        let documents = trait_filter_documents(&traits).unwrap();
+        assert_eq!(
+            documents,
+            vec![
+                json!([{ "trait_type": "Eyes", "value": "Laser" }, { "trait_type": "Hat", "value": "Cap" }]),
+                json!([{ "trait_type": "Eyes", "value": "Laser" }, { "trait_type": "Hat", "value": "Crown" }]),
+            ]
+        );
+    }
+
+    #[test]
+    fn numeric_trait_values_also_match_json_numbers() {
+        let documents = trait_filter_documents(&["Level:5".to_string()]).unwrap();
+        assert_eq!(
+            documents,
+            vec![
+                json!([{ "trait_type": "Level", "value": "5" }]),
+                json!([{ "trait_type": "Level", "value": 5 }]),
+            ]
+        );
+    }
+
+    #[test]
+    fn text_filter_matches_partial_words_literally() {
+        assert_eq!(like_pattern("pun"), "%pun%");
+        assert_eq!(like_pattern("50%_off"), "%50\\%\\_off%");
+    }
+
+    #[test]
+    fn malformed_trait_filter_is_rejected() {
+        assert!(matches!(trait_filter_documents(&["Hat".to_string()]), Err(ApiError::BadRequest(_))));
+        assert!(matches!(trait_filter_documents(&["Hat:".to_string()]), Err(ApiError::BadRequest(_))));
+    }
+}
@@ -0,0 +1,234 @@
+// Current holders and transfer history of tokens, and the tokens an address holds, all
+// read from the transfers and token_balances tables the metadata worker maintains.
+
+use axum::{extract::{Path, Query, State}, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::error::ApiError;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountNftsParams {
+    pub chain: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenOwner {
+    pub owner: String,
+    pub balance: String, // decimal; 1 for ERC-721
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransferRecord {
+    pub from_address: Option<String>, // null for the mint
+    pub to_address: Option<String>,   // null for a burn
+    pub amount: String,
+    pub tx_hash: String,
+    pub block_number: i64,
+    pub log_index: i64,
+    pub batch_index: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OwnedNft {
+    pub chain: String,
+    pub contract_address: String,
+    pub token_id: String,
+    pub balance: String,
+    // Null when the token's metadata has not been indexed
+    pub name: Option<String>,
+    pub cached_image_url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    // Pass this back as `cursor` to fetch the next page; null once the list is exhausted.
+    pub next_cursor: Option<String>,
+}
+
+// Splits a `a:b:c` cursor into its parts; `parts` is the number expected.
+fn cursor_parts(cursor: Option<&str>, parts: usize) -> Result<Option<Vec<&str>>, ApiError> {
+    let Some(cursor) = cursor.filter(|c| !c.is_empty()) else {
+        return Ok(None);
+    };
+    let split: Vec<&str> = cursor.split(':').collect();
+    if split.len() != parts || split.iter().any(|part| part.is_empty()) {
+        return Err(ApiError::BadRequest(format!("Invalid cursor '{}'", cursor)));
+    }
+    Ok(Some(split))
+}
+
+fn parse_cursor_number<T: std::str::FromStr>(part: &str) -> Result<T, ApiError> {
+    part.parse().map_err(|_| ApiError::BadRequest(format!("Invalid cursor part '{}'", part)))
+}
+
+// GET /nfts/:chain/:contract/:token_id/owners: addresses with a positive balance, by
+// address. An ERC-721 token has a single owner; unknown tokens have none.
+#[axum::debug_handler]
+pub async fn list_owners(
+    State(pool): State<PgPool>,
+    Path((chain, contract_address, token_id)): Path<(String, String, String)>,
+    Query(params): Query<PageParams>,
+) -> Result<Json<Page<TokenOwner>>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let after_owner = cursor_parts(params.cursor.as_deref(), 1)?.map(|parts| parts[0].to_lowercase());
+
+    let mut owners = sqlx::query_as!(
+        TokenOwner,
+        r#"
+        SELECT owner, balance::text AS "balance!"
+        FROM token_balances
+        WHERE chain = $1 AND contract_address = $2 AND token_id = $3 AND balance > 0
+          AND ($4::text IS NULL OR owner > $4)
+        ORDER BY owner
+        LIMIT $5
+        "#,
+        chain.to_lowercase(),
+        contract_address.to_lowercase(),
+        token_id,
+        after_owner,
+        limit + 1
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let next_cursor = if owners.len() as i64 > limit {
+        owners.truncate(limit as usize);
+        owners.last().map(|owner| owner.owner.clone())
+    } else {
+        None
+    };
+    Ok(Json(Page { items: owners, next_cursor }))
+}
+
+// GET /nfts/:chain/:contract/:token_id/history: the token's transfers, newest first.
+#[axum::debug_handler]
+pub async fn list_history(
+    State(pool): State<PgPool>,
+    Path((chain, contract_address, token_id)): Path<(String, String, String)>,
+    Query(params): Query<PageParams>,
+) -> Result<Json<Page<TransferRecord>>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    // The cursor is the block:log_index:batch_index of the last transfer returned.
+    let (before_block, before_log, before_batch) = match cursor_parts(params.cursor.as_deref(), 3)? {
+        Some(parts) => (
+            Some(parse_cursor_number::<i64>(parts[0])?),
+            Some(parse_cursor_number::<i64>(parts[1])?),
+            Some(parse_cursor_number::<i32>(parts[2])?),
+        ),
+        None => (None, None, None),
+    };
+
+    let mut transfers = sqlx::query_as!(
+        TransferRecord,
+        r#"
+        SELECT from_address, to_address, amount::text AS "amount!", tx_hash, block_number, log_index, batch_index
+        FROM transfers
+        WHERE chain = $1 AND contract_address = $2 AND token_id = $3
+          AND ($4::bigint IS NULL OR (block_number, log_index, batch_index) < ($4::bigint, $5::bigint, $6::int))
+        ORDER BY block_number DESC, log_index DESC, batch_index DESC
+        LIMIT $7
+        "#,
+        chain.to_lowercase(),
+        contract_address.to_lowercase(),
+        token_id,
+        before_block,
+        before_log,
+        before_batch,
+        limit + 1
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let next_cursor = if transfers.len() as i64 > limit {
+        transfers.truncate(limit as usize);
+        transfers.last().map(|t| format!("{}:{}:{}", t.block_number, t.log_index, t.batch_index))
+    } else {
+        None
+    };
+    Ok(Json(Page { items: transfers, next_cursor }))
+}
+
+// GET /accounts/:address/nfts: tokens the address holds, on every chain unless `chain` is given.
+#[axum::debug_handler]
+pub async fn list_account_nfts(
+    State(pool): State<PgPool>,
+    Path(address): Path<String>,
+    Query(params): Query<AccountNftsParams>,
+) -> Result<Json<Page<OwnedNft>>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let chain = params.chain.map(|c| c.to_lowercase()).filter(|c| !c.is_empty());
+    // The cursor is the chain:contract:token_id of the last token returned.
+    let (after_chain, after_contract, after_token) = match cursor_parts(params.cursor.as_deref(), 3)? {
+        Some(parts) => (Some(parts[0].to_string()), Some(parts[1].to_string()), Some(parts[2].to_string())),
+        None => (None, None, None),
+    };
+
+    let mut nfts = sqlx::query_as!(
+        OwnedNft,
+        r#"
+        SELECT
+            b.chain,
+            b.contract_address,
+            b.token_id,
+            b.balance::text AS "balance!",
+            nm.name AS "name?",
+            img_media.cached_url AS "cached_image_url?"
+        FROM token_balances b
+        LEFT JOIN nft_metadata nm ON nm.chain = b.chain
+                                 AND nm.contract_address = b.contract_address
+                                 AND nm.token_id = b.token_id
+                                 AND NOT nm.orphaned
+        LEFT JOIN nft_media img_media ON img_media.chain = nm.chain
+                                     AND img_media.contract_address = nm.contract_address
+                                     AND img_media.token_id = nm.token_id
+                                     AND img_media.media_type = 'image'
+        WHERE b.owner = $1 AND b.balance > 0
+          AND ($2::text IS NULL OR b.chain = $2)
+          AND ($3::text IS NULL OR (b.chain, b.contract_address, b.token_id) > ($3::text, $4::text, $5::text))
+        ORDER BY b.chain, b.contract_address, b.token_id
+        LIMIT $6
+        "#,
+        address.to_lowercase(),
+        chain,
+        after_chain,
+        after_contract,
+        after_token,
+        limit + 1
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let next_cursor = if nfts.len() as i64 > limit {
+        nfts.truncate(limit as usize);
+        nfts.last().map(|nft| format!("{}:{}:{}", nft.chain, nft.contract_address, nft.token_id))
+    } else {
+        None
+    };
+    Ok(Json(Page { items: nfts, next_cursor }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_needs_every_part() {
+        assert_eq!(cursor_parts(Some("100:3:0"), 3).unwrap(), Some(vec!["100", "3", "0"]));
+        assert_eq!(cursor_parts(Some(""), 3).unwrap(), None);
+        assert!(matches!(cursor_parts(Some("100:3"), 3), Err(ApiError::BadRequest(_))));
+        assert!(matches!(cursor_parts(Some("100::0"), 3), Err(ApiError::BadRequest(_))));
+    }
+}
@@ -0,0 +1,118 @@
+// POST /nfts/:chain/:contract/:token_id/refresh queues a refresh job for the metadata
+// worker, which reads the token URI from the contract again (so a reveal that changed
+// the base URI is picked up), fetches the metadata and records it if it changed. Each
+// token can be refreshed once per cooldown (REFRESH_COOLDOWN_SECS, default 300); the
+// last request per token is kept in metadata_refresh_requests, so the limit holds across
+// API instances.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use common::RefreshJob;
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use sqlx::PgPool;
+use std::env;
+use std::time::Duration;
+
+use crate::error::ApiError;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct RefreshQueue {
+    producer: FutureProducer,
+    topic: String,
+    cooldown: Duration,
+}
+
+impl RefreshQueue {
+    // None when KAFKA_BROKERS is not set; refresh requests are then answered with 503.
+    pub fn from_env() -> Option<Self> {
+        let brokers = env::var("KAFKA_BROKERS").ok()?;
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .set("security.protocol", env::var("KAFKA_SECURITY_PROTOCOL").unwrap_or_else(|_| "SASL_SSL".to_string()))
+            .set("sasl.mechanisms", env::var("KAFKA_SASL_MECHANISMS").unwrap_or_else(|_| "PLAIN".to_string()))
+            .set("sasl.username", env::var("KAFKA_SASL_USERNAME").expect("KAFKA_SASL_USERNAME must be set with KAFKA_BROKERS"))
+            .set("sasl.password", env::var("KAFKA_SASL_PASSWORD").expect("KAFKA_SASL_PASSWORD must be set with KAFKA_BROKERS"))
+            .create()
+            .expect("Failed to create Kafka producer");
+        let cooldown_secs = env::var("REFRESH_COOLDOWN_SECS").ok().and_then(|secs| secs.parse().ok()).unwrap_or(300);
+        Some(RefreshQueue {
+            producer,
+            topic: env::var("KAFKA_REFRESH_TOPIC").unwrap_or_else(|_| "nft_refresh_jobs".to_string()),
+            cooldown: Duration::from_secs(cooldown_secs),
+        })
+    }
+}
+
+#[axum::debug_handler(state = crate::AppState)]
+pub async fn refresh_nft(
+    State(pool): State<PgPool>,
+    State(queue): State<Option<RefreshQueue>>,
+    Path((chain, contract_address, token_id)): Path<(String, String, String)>,
+) -> Result<(StatusCode, Json<RefreshJob>), ApiError> {
+    let Some(queue) = queue else {
+        return Err(ApiError::Unavailable("Metadata refresh is not enabled".to_string()));
+    };
+    let chain = chain.to_lowercase();
+    let contract_address = contract_address.to_lowercase();
+
+    let known = sqlx::query_scalar!(
+        r#"SELECT EXISTS (
+               SELECT 1 FROM nft_metadata
+               WHERE chain = $1 AND contract_address = $2 AND token_id = $3 AND NOT orphaned
+           ) AS "known!""#,
+        chain,
+        contract_address,
+        token_id
+    )
+    .fetch_one(&pool)
+    .await?;
+    if !known {
+        return Err(ApiError::NotFound(format!("NFT {}/{}/{} not found", chain, contract_address, token_id)));
+    }
+
+    // Claims the token's cooldown in its own short statement, so no lock is held while
+    // the job is sent; of concurrent requests only one gets past the cooldown check.
+    let accepted = sqlx::query!(
+        r#"INSERT INTO metadata_refresh_requests (chain, contract_address, token_id, requested_at)
+           VALUES ($1, $2, $3, NOW())
+           ON CONFLICT (chain, contract_address, token_id) DO UPDATE SET requested_at = NOW()
+           WHERE metadata_refresh_requests.requested_at <= NOW() - make_interval(secs => $4)
+           RETURNING 1 AS "accepted!""#,
+        chain,
+        contract_address,
+        token_id,
+        queue.cooldown.as_secs_f64()
+    )
+    .fetch_optional(&pool)
+    .await?
+    .is_some();
+    if !accepted {
+        return Err(ApiError::TooManyRequests(format!(
+            "A refresh of this token was requested in the last {} seconds",
+            queue.cooldown.as_secs()
+        )));
+    }
+
+    let job = RefreshJob { chain, contract_address, token_id, metadata_uri: None, to_token_id: None };
+    let payload = serde_json::to_string(&job).expect("refresh job serializes");
+    let record = FutureRecord::to(&queue.topic).payload(&payload).key(&job.contract_address);
+    if let Err((e, _)) = queue.producer.send(record, SEND_TIMEOUT).await {
+        eprintln!("Failed to queue refresh job: {}", e);
+        // Give the cooldown back, so a failed send does not use it up. The row is ours:
+        // nobody else can claim it before the cooldown is over.
+        sqlx::query!(
+            "DELETE FROM metadata_refresh_requests WHERE chain = $1 AND contract_address = $2 AND token_id = $3",
+            job.chain,
+            job.contract_address,
+            job.token_id
+        )
+        .execute(&pool)
+        .await?;
+        return Err(ApiError::Unavailable("Could not queue the refresh, try again later".to_string()));
+    }
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
@@ -0,0 +1,156 @@
+use axum::{extract::{Query, State}, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+
+use crate::error::ApiError;
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+// ts_headline wraps matched terms in these private-use characters (stripped from the
+// text first); `highlight_html` escapes the rest and turns them into <mark> tags, so
+// `name_highlight` and `snippet` are safe to render as HTML.
+const HIGHLIGHT_START: char = '\u{E000}';
+const HIGHLIGHT_STOP: char = '\u{E001}';
+const HIGHLIGHT_MARKERS: &str = "\u{E000}\u{E001}";
+const HEADLINE_OPTIONS: &str = "StartSel=\u{E000}, StopSel=\u{E001}, MaxWords=35, MinWords=15, MaxFragments=2";
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    pub q: Option<String>, // web-search syntax: quoted phrases, `or`, `-excluded`
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    // Burned tokens are left out unless this is true.
+    #[serde(default)]
+    pub include_burned: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub id: i32,
+    pub contract_address: String,
+    pub token_id: String,
+    pub chain: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub attributes: Option<Value>,
+    pub cached_image_url: Option<String>,
+    pub image_renditions: Option<Value>,
+    pub burned: bool,
+    pub rank: f32,
+    pub name_highlight: Option<String>,
+    pub snippet: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub query: String,
+    pub results: Vec<SearchHit>,
+    // Offset of the next page; null when this page was the last one.
+    pub next_offset: Option<i64>,
+}
+
+#[axum::debug_handler]
+pub async fn search_nfts(
+    State(pool): State<PgPool>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<SearchResults>, ApiError> {
+    let query = params.q.unwrap_or_default().trim().to_string();
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    if query.is_empty() {
+        return Ok(Json(SearchResults { query, results: Vec::new(), next_offset: None }));
+    }
+
+    // Results are ordered by relevance, so pagination is offset-based; one extra row
+    // tells us whether another page exists.
+    let mut results = sqlx::query_as!(
+        SearchHit,
+        r#"
+        SELECT
+            nm.id,
+            nm.contract_address,
+            nm.token_id,
+            nm.chain,
+            nm.name,
+            nm.description,
+            nm.attributes,
+            img_media.cached_url AS "cached_image_url?",
+            (SELECT jsonb_object_agg(r.width::text, r.cached_url)
+             FROM media_renditions r
+             WHERE r.content_hash = img_media.content_hash) AS "image_renditions?",
+            nm.burned,
+            ts_rank_cd(nm.search_vector, query) AS "rank!",
+            ts_headline('english', translate(coalesce(nm.name, ''), $6, ''), query, $2) AS name_highlight,
+            ts_headline('english', translate(coalesce(nm.description, ''), $6, ''), query, $2) AS snippet
+        FROM
+            nft_metadata nm
+        CROSS JOIN
+            websearch_to_tsquery('english', $1) AS query
+        LEFT JOIN
+            nft_media img_media ON nm.chain = img_media.chain
+                                AND nm.contract_address = img_media.contract_address
+                                AND nm.token_id = img_media.token_id
+                                AND img_media.media_type = 'image'
+        WHERE nm.search_vector @@ query AND NOT nm.orphaned AND ($5 OR NOT nm.burned)
+        ORDER BY ts_rank_cd(nm.search_vector, query) DESC, nm.id DESC
+        LIMIT $3 OFFSET $4
+        "#,
+        query,
+        HEADLINE_OPTIONS,
+        limit + 1,
+        offset,
+        params.include_burned,
+        HIGHLIGHT_MARKERS
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    let next_offset = if results.len() as i64 > limit {
+        results.truncate(limit as usize);
+        Some(offset + limit)
+    } else {
+        None
+    };
+
+    for hit in &mut results {
+        hit.name_highlight = hit.name_highlight.as_deref().map(highlight_html);
+        hit.snippet = hit.snippet.as_deref().map(highlight_html);
+    }
+
+    Ok(Json(SearchResults { query, results, next_offset }))
+}
+
+// HTML-escapes a ts_headline result, which is untrusted token text, and marks the matched terms.
+fn highlight_html(headline: &str) -> String {
+    let mut html = String::with_capacity(headline.len());
+    for c in headline.chars() {
+        match c {
+            HIGHLIGHT_START => html.push_str("<mark>"),
+            HIGHLIGHT_STOP => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlights_are_escaped_except_the_marks() {
+        let headline = "\u{E000}Cat\u{E001} <img src=x onerror=\"alert('x')\"> & dog";
+        assert_eq!(
+            highlight_html(headline),
+            "<mark>Cat</mark> &lt;img src=x onerror=&quot;alert(&#39;x&#39;)&quot;&gt; &amp; dog"
+        );
+    }
+}
@@ -36,7 +36,7 @@ export const useNFTData = (searchQuery: string) => {
   const [nfts, setNfts] = useState<NFT[]>([]);
   const [loading, setLoading] = useState(false);
   const [hasMore, setHasMore] = useState(true);
-  const [page, setPage] = useState(1);
+  const [cursor, setCursor] = useState<number | null>(null);
 
   const pageSize = 12;
 
@@ -67,68 +67,39 @@ export const useNFTData = (searchQuery: string) => {
     return { terms, filters };
   }, []);
 
-  const filterNFTs = useCallback((nfts: NFT[], query: string) => {
-    if (!query.trim()) return nfts;
-    
+  // Translate the search bar syntax into /nfts query parameters so filtering
+  // and pagination happen server-side over the whole index.
+  const buildQueryParams = useCallback((query: string, pageCursor: number | null) => {
     const { terms, filters } = parseSearchQuery(query);
-    
-    return nfts.filter(nft => {
-      if (filters.blockchain && nft.chain?.toLowerCase() !== filters.blockchain) {
-        return false;
-      }
-      
-      if (filters.fileType) {
-        const imageTypes = ['image', 'img', 'jpeg', 'jpg', 'png', 'gif', 'webp'];
-        const videoTypes = ['video', 'mp4', 'avi', 'mov', 'webm'];
-        
-        // Use nft.image for filtering, as it's the resolved URL
-        if (filters.fileType === 'image' && !imageTypes.some(type => 
-          nft.image?.toLowerCase().includes(type) || nft.name.toLowerCase().includes(type)
-        )) {
-          return false;
-        }
-        
-        if (filters.fileType === 'video' && !videoTypes.some(type => 
-          nft.image?.toLowerCase().includes(type) || nft.name.toLowerCase().includes(type)
-        )) {
-          return false;
-        }
-      }
-      
-      if (filters.tokenId && !nft.id.includes(filters.tokenId) && 
-          !nft.name.toLowerCase().includes(`#${filters.tokenId}`)) {
-        return false;
-      }
-      
-      if (terms.length > 0) {
-        const searchableText = [
-          nft.name,
-          nft.collection || '',
-          nft.description,
-          nft.chain || '',
-          ...nft.attributes.map(attr => `${attr.trait_type} ${attr.value}`)
-        ].join(' ').toLowerCase();
-        
-        const matchesAllTerms = terms.every(term => searchableText.includes(term));
-        if (!matchesAllTerms) return false;
-      }
-      
-      return true;
-    });
+    const params = new URLSearchParams();
+    params.set('limit', String(pageSize));
+
+    if (filters.blockchain) params.set('chain', filters.blockchain);
+    if (filters.tokenId) params.set('token_id', filters.tokenId);
+    if (filters.fileType) {
+      const videoTypes = ['video', 'mp4', 'avi', 'mov', 'webm', 'animation'];
+      params.set('media_type', videoTypes.includes(filters.fileType) ? 'animation' : 'image');
+    }
+    if (terms.length > 0) params.set('q', terms.join(' '));
+    if (pageCursor !== null) params.set('cursor', String(pageCursor));
+
+    return params;
   }, [parseSearchQuery]);
 
   const loadNFTs = useCallback(async (reset: boolean = false) => {
     setLoading(true);
     try {
       // Ensure API_BASE_URL is correct (e.g., "https://api.example.com", not "https://api.example.com/nfts")
-      const response = await fetch(`${API_BASE_URL}/nfts`); 
+      const params = buildQueryParams(searchQuery, reset ? null : cursor);
+      const response = await fetch(`${API_BASE_URL}/nfts?${params.toString()}`);
       
       if (!response.ok) {
         const errorText = await response.text();
         throw new Error(`HTTP error! status: ${response.status}, message: ${errorText}`);
       }
 
-      const backendNFTs = await response.json();
+      // The API responds with { items, next_cursor }
+      const { items: backendNFTs, next_cursor: nextCursor } = await response.json();
 
       const mappedNFTs: NFT[] = backendNFTs.map((nft: any) => {
         let imageUrl = nft.cached_image_url; // 1. Try cached_image_url first (S3)
@@ -173,26 +144,20 @@ export const useNFTData = (searchQuery: string) => {
         };
       });
 
-      const filtered = filterNFTs(mappedNFTs, searchQuery);
-      const startIndex = reset ? 0 : (page - 1) * pageSize;
-      const endIndex = startIndex + pageSize;
-      const newNFTs = filtered.slice(startIndex, endIndex);
-
       if (reset) {
-        setNfts(newNFTs);
-        setPage(2);
+        setNfts(mappedNFTs);
       } else {
-        setNfts(prev => [...prev, ...newNFTs]);
-        setPage(prev => prev + 1);
+        setNfts(prev => [...prev, ...mappedNFTs]);
       }
-      setHasMore(endIndex < filtered.length);
+      setCursor(nextCursor ?? null);
+      setHasMore(nextCursor !== null && nextCursor !== undefined);
     } catch (e) {
       console.error("Failed to fetch NFTs:", e);
       setNfts([]);
       setHasMore(false);
     }
     setLoading(false);
-  }, [searchQuery, page, filterNFTs]);
+  }, [searchQuery, cursor, buildQueryParams]);
 
   const loadMore = useCallback(() => {
     if (!loading && hasMore) {
@@ -201,7 +166,7 @@ export const useNFTData = (searchQuery: string) => {
   }, [loading, hasMore, loadNFTs]);
 
   useEffect(() => {
-    setPage(1);
+    setCursor(null);
     loadNFTs(true);
   }, [searchQuery]);
 
@@ -1,25 +1,14 @@
-use ethers::prelude::*;
-use ethers::providers::{Provider, Http};
-use ethers::types::{U256};
+use common::chain::ChainAdapter;
+use common::evm::{self, EvmAdapter, EvmChainConfig};
 use std::env;
-use std::sync::Arc;
-use common::NftMintJob;
+use common::{CollectionJob, NftMintJob};
 use rdkafka::config::ClientConfig;
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use std::time::Duration;
 use anyhow::Result;
 
-// Minimal ERC721 ABI with totalSupply and tokenURI
-abigen!(
-    ERC721,
-    r#"[
-        function totalSupply() external view returns (uint256)
-        function tokenURI(uint256 tokenId) external view returns (string)
-    ]"#,
-);
-
-async fn produce_job(producer: &FutureProducer, topic: &str, job: NftMintJob) {
-    let job_payload = match serde_json::to_string(&job) {
+async fn produce_job<T: serde::Serialize>(producer: &FutureProducer, topic: &str, key: &str, job: &T) {
+    let job_payload = match serde_json::to_string(job) {
         Ok(p) => p,
         Err(e) => {
             eprintln!("[ERROR] Failed to serialize job: {}", e);
@@ -29,12 +18,12 @@ async fn produce_job(producer: &FutureProducer, topic: &str, job: NftMintJob) {
 
     let record = FutureRecord::to(topic)
         .payload(&job_payload)
-        .key(&job.contract_address);
+        .key(key);
 
     if let Err((e, _)) = producer.send(record, Duration::from_secs(0)).await {
         eprintln!("[ERROR] Failed to send job to Kafka: {}", e);
     } else {
-        println!("[SUCCESS] Sent job for Token ID: {}", job.token_id);
+        println!("[SUCCESS] Sent job to {} (key: {})", topic, key);
     }
 }
 
@@ -43,21 +32,29 @@ async fn main() -> Result<()> {
     dotenvy::dotenv().ok();
 
     // --- Configuration ---
-    let http_url = env::var("ETHEREUM_HTTP_URL").expect("ETHEREUM_HTTP_URL must be set");
+    // Any EVM chain the listener supports; the RPC URL comes from <CHAIN>_HTTP_URL
+    // (e.g. ETHEREUM_HTTP_URL) and the chain id from <CHAIN>_CHAIN_ID for unknown chains.
+    let chain = env::var("CHAIN").unwrap_or_else(|_| "ethereum".to_string()).to_lowercase();
+    let prefix = chain.to_uppercase();
+    let http_url = env::var(format!("{prefix}_HTTP_URL")).unwrap_or_else(|_| panic!("{prefix}_HTTP_URL must be set"));
+    let chain_id = match env::var(format!("{prefix}_CHAIN_ID")) {
+        Ok(id) => id.parse()?,
+        Err(_) => evm::known_chain_id(&chain).unwrap_or_else(|| panic!("{prefix}_CHAIN_ID must be set for chain '{chain}'")),
+    };
     let kafka_brokers = env::var("KAFKA_BROKERS").expect("KAFKA_BROKERS must be set");
     let kafka_topic = env::var("KAFKA_TOPIC").unwrap_or_else(|_| "nft_mint_jobs".to_string());
+    let collection_topic = env::var("KAFKA_COLLECTION_TOPIC").unwrap_or_else(|_| "nft_collection_jobs".to_string());
     
     // Target NFT contracts to backfill (comma-separated)
     let contracts_to_backfill_str = env::var("BACKFILL_CONTRACTS")
         .expect("BACKFILL_CONTRACTS must be set (e.g., '0xAddress1,0xAddress2')");
-    let contract_addresses: Vec<&str> = contracts_to_backfill_str.split(',').collect();
+    let contract_addresses: Vec<String> = contracts_to_backfill_str.split(',').map(|a| a.trim().to_lowercase()).collect();
 
     let kafka_username = env::var("KAFKA_USERNAME").expect("KAFKA_USERNAME must be set");
     let kafka_password = env::var("KAFKA_PASSWORD").expect("KAFKA_PASSWORD must be set");
 
     // --- Setup Connections ---
-    let provider = Provider::<Http>::try_from(http_url)?;
-    let client = Arc::new(provider);
+    let adapter = EvmAdapter::connect_http(EvmChainConfig { name: chain, chain_id, rpc_url: http_url }).await?;
 
     let producer: FutureProducer = ClientConfig::new()
         .set("bootstrap.servers", &kafka_brokers)
@@ -71,40 +68,50 @@ async fn main() -> Result<()> {
 
     // --- Main Backfill Logic ---
     for address_str in contract_addresses {
-        let address: Address = address_str.parse()?;
-        let contract = ERC721::new(address, client.clone());
-
         println!("\n[INFO] Starting backfill for contract: {}", address_str);
 
-        // 1. Get total supply to know how many tokens to loop through
-        let total_supply = match contract.total_supply().call().await {
-            Ok(supply) => supply,
+        // 1. Enumerate the collection's token ids (1..=totalSupply)
+        let token_ids = match adapter.enumerate_collection(&address_str).await {
+            Ok(token_ids) => token_ids,
             Err(e) => {
-                eprintln!("[ERROR] Could not fetch total supply for {}: {}", address_str, e);
+                eprintln!("[ERROR] Could not enumerate tokens for {}: {}", address_str, e);
                 continue; // Skip to next contract
             }
         };
-        println!("[INFO] Total supply for {} is: {}", address_str, total_supply);
-        
-        // 2. Loop from token ID 1 (or 0, depending on the contract) up to totalSupply
-        for i in 1..=total_supply.as_u64() {
-            let token_id = U256::from(i);
-
-            // 3. Fetch the token URI
-            let token_uri_result = contract.token_uri(token_id).call().await;
+        println!("[INFO] Total supply for {} is: {}", address_str, token_ids.len());
+
+        // Record the collection itself; name, symbol and contractURI are optional getters
+        let info = adapter.collection_info(&address_str).await;
+        let collection = CollectionJob {
+            contract_address: address_str.clone(),
+            chain: adapter.chain().to_string(),
+            name: info.name,
+            symbol: info.symbol,
+            token_standard: Some("erc721".to_string()),
+            total_supply: info.total_supply,
+            first_seen_block: None,
+            contract_uri: info.contract_uri,
+        };
+        println!("[QUEUING] Collection job for Contract: {}", address_str);
+        produce_job(&producer, &collection_topic, &collection.contract_address, &collection).await;
 
-            match token_uri_result {
+        for token_id in token_ids {
+            // 2. Fetch the token URI
+            match adapter.token_uri(&address_str, &token_id, "erc721").await {
                 Ok(metadata_uri) => {
-                    // 4. Create and send the job
+                    // 3. Create and send the job
                     let job = NftMintJob {
-                        contract_address: format!("{:?}", address),
-                        token_id: token_id.to_string(),
-                        chain: "ethereum".to_string(),
+                        contract_address: address_str.clone(),
+                        token_id: token_id.clone(),
+                        chain: adapter.chain().to_string(),
                         metadata_uri: Some(metadata_uri),
+                        // Backfilled tokens come from current contract state, not a mint log.
+                        block_number: None,
+                        block_hash: None,
                     };
 
-                    println!("[QUEUING] Job for Contract: {:?}, Token ID: {}", address, token_id);
-                    produce_job(&producer, &kafka_topic, job).await;
+                    println!("[QUEUING] Job for Contract: {}, Token ID: {}", address_str, token_id);
+                    produce_job(&producer, &kafka_topic, &job.contract_address, &job).await;
                 }
                 Err(e) => {
                     eprintln!("[ERROR] Could not fetch token URI for token {}: {}", token_id, e);
@@ -0,0 +1,329 @@
+// `ChainAdapter` for EVM chains (Ethereum, Polygon, Base, Arbitrum, ...), which all share
+// the ERC-721 / ERC-1155 event and getter ABI and differ only in chain id and RPC URL.
+
+use async_trait::async_trait;
+use ethers::abi::{self, ParamType, Token};
+use ethers::contract::abigen;
+use ethers::providers::{Http, JsonRpcClient, Middleware, Provider, ProviderError, Ws};
+use ethers::types::{Address, Filter, Log, H256, U256};
+use futures::stream::{BoxStream, StreamExt};
+use std::sync::Arc;
+
+use crate::chain::{ChainAdapter, ChainEvent, CollectionInfo, EventKind};
+
+// ERC-721 ABI fragment for tokenURI
+abigen!(ERC721, r#"[
+    function tokenURI(uint256 tokenId) external view returns (string)
+]"#);
+
+// ERC-1155 ABI fragment (no tokenURI standard, but some contracts implement uri(uint256))
+abigen!(ERC1155, r#"[
+    function uri(uint256 id) external view returns (string)
+]"#);
+
+// Contract-level getters shared by most NFT contracts (all optional, calls may revert)
+abigen!(NFTCollection, r#"[
+    function name() external view returns (string)
+    function symbol() external view returns (string)
+    function totalSupply() external view returns (uint256)
+    function contractURI() external view returns (string)
+]"#);
+
+const TRANSFER: &str = "Transfer(address,address,uint256)";
+const TRANSFER_SINGLE: &str = "TransferSingle(address,address,address,uint256,uint256)";
+const TRANSFER_BATCH: &str = "TransferBatch(address,address,address,uint256[],uint256[])";
+const METADATA_UPDATE: &str = "MetadataUpdate(uint256)";
+const BATCH_METADATA_UPDATE: &str = "BatchMetadataUpdate(uint256,uint256)";
+const URI: &str = "URI(string,uint256)";
+
+// Metadata update ranges up to this many ids are expanded here, since the listener reads
+// the URI of every expanded token; wider ones (often 0..type(uint256).max for
+// "everything") are left to the worker, which knows the collection's indexed tokens.
+const MAX_EXPANDED_RANGE: u64 = 100;
+
+// Collections with a larger totalSupply are not enumerated one id at a time.
+const MAX_ENUMERATED_SUPPLY: u64 = 1_000_000;
+
+#[derive(Debug, Clone)]
+pub struct EvmChainConfig {
+    pub name: String,
+    pub chain_id: u64,
+    pub rpc_url: String,
+}
+
+// Chain ids of the networks that can be configured by name alone.
+pub fn known_chain_id(name: &str) -> Option<u64> {
+    match name {
+        "ethereum" => Some(1),
+        "polygon" => Some(137),
+        "base" => Some(8453),
+        "arbitrum" => Some(42161),
+        _ => None,
+    }
+}
+
+// How a transport turns a log filter into a live stream: a subscription over WebSocket,
+// eth_newFilter polling over HTTP.
+#[async_trait]
+pub trait LogTransport: JsonRpcClient + Sized + 'static {
+    async fn log_stream<'a>(provider: &'a Provider<Self>, filter: &Filter) -> Result<BoxStream<'a, Log>, ProviderError>;
+}
+
+#[async_trait]
+impl LogTransport for Ws {
+    async fn log_stream<'a>(provider: &'a Provider<Self>, filter: &Filter) -> Result<BoxStream<'a, Log>, ProviderError> {
+        Ok(provider.subscribe_logs(filter).await?.boxed())
+    }
+}
+
+#[async_trait]
+impl LogTransport for Http {
+    async fn log_stream<'a>(provider: &'a Provider<Self>, filter: &Filter) -> Result<BoxStream<'a, Log>, ProviderError> {
+        Ok(provider.watch(filter).await?.boxed())
+    }
+}
+
+pub struct EvmAdapter<P: JsonRpcClient> {
+    config: EvmChainConfig,
+    provider: Arc<Provider<P>>,
+}
+
+impl EvmAdapter<Ws> {
+    pub async fn connect(config: EvmChainConfig) -> anyhow::Result<Self> {
+        let provider = Provider::<Ws>::connect(config.rpc_url.as_str()).await?;
+        Self::verified(config, provider).await
+    }
+}
+
+impl EvmAdapter<Http> {
+    pub async fn connect_http(config: EvmChainConfig) -> anyhow::Result<Self> {
+        let provider = Provider::<Http>::try_from(config.rpc_url.as_str())?;
+        Self::verified(config, provider).await
+    }
+}
+
+impl<P: JsonRpcClient + 'static> EvmAdapter<P> {
+    // Refuses RPC URLs that serve a different chain than the one they are configured for.
+    async fn verified(config: EvmChainConfig, provider: Provider<P>) -> anyhow::Result<Self> {
+        let chain_id = provider.get_chainid().await?;
+        if chain_id != U256::from(config.chain_id) {
+            anyhow::bail!("RPC URL for {} serves chain id {}, expected {}", config.name, chain_id, config.chain_id);
+        }
+        Ok(EvmAdapter { config, provider: Arc::new(provider) })
+    }
+}
+
+#[async_trait]
+impl<P: LogTransport> ChainAdapter for EvmAdapter<P> {
+    fn chain(&self) -> &str {
+        &self.config.name
+    }
+
+    async fn head(&self) -> anyhow::Result<u64> {
+        Ok(self.provider.get_block_number().await?.as_u64())
+    }
+
+    async fn block_hash(&self, block_number: u64) -> anyhow::Result<Option<String>> {
+        let block = self.provider.get_block(block_number).await?;
+        Ok(block.and_then(|b| b.hash).map(|hash| format!("{:?}", hash)))
+    }
+
+    async fn subscribe_events(&self) -> anyhow::Result<BoxStream<'_, ChainEvent>> {
+        let logs = P::log_stream(&self.provider, &event_filter()).await?;
+        Ok(logs.filter_map(|log| async move { event_from_log(&log) }).boxed())
+    }
+
+    // Without an address filter, topic0 also matches every ERC-20 Transfer, so a busy range
+    // can exceed the result or response-size cap of the RPC provider. The window is halved
+    // on such errors, down to one block, and stays at the size that worked.
+    async fn events_in_range(&self, from_block: u64, to_block: u64) -> anyhow::Result<Vec<ChainEvent>> {
+        let mut events = Vec::new();
+        let mut window = to_block.saturating_sub(from_block) + 1;
+        let mut start = from_block;
+        while start <= to_block {
+            let end = to_block.min(start + window - 1);
+            let filter = event_filter().from_block(start).to_block(end);
+            match self.provider.get_logs(&filter).await {
+                Ok(logs) => {
+                    events.extend(logs.iter().filter_map(event_from_log));
+                    start = end + 1;
+                }
+                Err(e) if window > 1 && is_log_limit_error(&e.to_string()) => window /= 2,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(events)
+    }
+
+    async fn token_uri(&self, contract_address: &str, token_id: &str, token_standard: &str) -> anyhow::Result<String> {
+        let address: Address = contract_address.parse()?;
+        let id = U256::from_dec_str(token_id)?;
+        let uri = if token_standard == "erc1155" {
+            ERC1155::new(address, self.provider.clone()).uri(id).call().await?
+        } else {
+            ERC721::new(address, self.provider.clone()).token_uri(id).call().await?
+        };
+        Ok(uri)
+    }
+
+    async fn collection_info(&self, contract_address: &str) -> CollectionInfo {
+        let Ok(address) = contract_address.parse::<Address>() else {
+            return CollectionInfo::default();
+        };
+        let contract = NFTCollection::new(address, self.provider.clone());
+        CollectionInfo {
+            name: contract.name().call().await.ok(),
+            symbol: contract.symbol().call().await.ok(),
+            total_supply: contract.total_supply().call().await.ok().map(|supply| supply.to_string()),
+            contract_uri: contract.contract_uri().call().await.ok(),
+        }
+    }
+
+    // Assumes sequential ids starting at 1, which holds for most ERC-721 drops.
+    async fn enumerate_collection(&self, contract_address: &str) -> anyhow::Result<Vec<String>> {
+        let address: Address = contract_address.parse()?;
+        let total_supply = NFTCollection::new(address, self.provider.clone()).total_supply().call().await?;
+        let supply = u64::try_from(total_supply).ok().filter(|&supply| supply <= MAX_ENUMERATED_SUPPLY);
+        let Some(supply) = supply else {
+            anyhow::bail!("total supply {} is too large to enumerate", total_supply);
+        };
+        Ok((1..=supply).map(|id| id.to_string()).collect())
+    }
+
+    async fn tokens_in_range(&self, _contract_address: &str, from_token_id: &str, to_token_id: &str) -> anyhow::Result<Option<Vec<String>>> {
+        let from = U256::from_dec_str(from_token_id)?;
+        let to = U256::from_dec_str(to_token_id)?;
+        if to < from {
+            return Ok(Some(Vec::new()));
+        }
+        if to - from >= U256::from(MAX_EXPANDED_RANGE) {
+            return Ok(None);
+        }
+        let width = (to - from).low_u64();
+        Ok(Some((0..=width).map(|offset| (from + offset).to_string()).collect()))
+    }
+}
+
+// Providers word their eth_getLogs limits differently, e.g. "query returned more than 10000
+// results", "Log response size exceeded", "block range is too wide".
+fn is_log_limit_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    ["more than", "too many", "size exceeded", "limit exceeded", "too large", "too wide"]
+        .iter()
+        .any(|hint| message.contains(hint))
+}
+
+fn event_signature(signature: &str) -> H256 {
+    H256::from_slice(&ethers::utils::keccak256(signature))
+}
+
+// ERC-721 and ERC-1155 transfer events, EIP-4906 metadata updates and ERC-1155 URI
+// changes. `Filter::event` replaces topic0 on every call, so the signatures are passed
+// together as one OR-ed topic0.
+fn event_filter() -> Filter {
+    Filter::new().topic0(vec![
+        event_signature(TRANSFER),
+        event_signature(TRANSFER_SINGLE),
+        event_signature(TRANSFER_BATCH),
+        event_signature(METADATA_UPDATE),
+        event_signature(BATCH_METADATA_UPDATE),
+        event_signature(URI),
+    ])
+}
+
+// Decodes a log into an event, or None when it is not one the indexer acts on.
+fn event_from_log(log: &Log) -> Option<ChainEvent> {
+    let signature = *log.topics.first()?;
+    let kind = if signature == event_signature(METADATA_UPDATE) {
+        // data: (tokenId)
+        let decoded = abi::decode(&[ParamType::Uint(256)], &log.data).ok()?;
+        let token_id = decoded.into_iter().next()?.into_uint()?.to_string();
+        EventKind::MetadataUpdate { from_token_id: token_id.clone(), to_token_id: token_id }
+    } else if signature == event_signature(BATCH_METADATA_UPDATE) {
+        // data: (fromTokenId, toTokenId)
+        let decoded = abi::decode(&[ParamType::Uint(256), ParamType::Uint(256)], &log.data).ok()?;
+        let mut bounds = decoded.into_iter().filter_map(Token::into_uint);
+        EventKind::MetadataUpdate {
+            from_token_id: bounds.next()?.to_string(),
+            to_token_id: bounds.next()?.to_string(),
+        }
+    } else if signature == event_signature(URI) {
+        // topics: [event, id], data: (value)
+        let token_id = U256::from_big_endian(log.topics.get(1)?.as_bytes()).to_string();
+        let decoded = abi::decode(&[ParamType::String], &log.data).ok()?;
+        EventKind::UriChanged { token_id, uri: decoded.into_iter().next()?.into_string()? }
+    } else {
+        transfer_from_log(log)?
+    };
+
+    Some(ChainEvent {
+        contract_address: format!("{:?}", log.address),
+        kind,
+        block_number: log.block_number?.as_u64(), // pending logs have no block yet
+        block_hash: format!("{:?}", log.block_hash?),
+        log_index: log.log_index?.as_u64(),
+        tx_hash: format!("{:?}", log.transaction_hash?),
+        removed: log.removed.unwrap_or(false),
+    })
+}
+
+// Decodes an ERC-721 or ERC-1155 transfer log, or None when it is not an NFT transfer.
+fn transfer_from_log(log: &Log) -> Option<EventKind> {
+    let signature = *log.topics.first()?;
+    let (token_standard, from, to, token_ids, amounts) = if signature == event_signature(TRANSFER) {
+        // topics: [event, from, to, tokenId]; ERC-20 Transfer has no 4th topic
+        if log.topics.len() != 4 {
+            return None;
+        }
+        let token_id = U256::from_big_endian(log.topics[3].as_bytes());
+        ("erc721", log.topics[1], log.topics[2], vec![token_id], vec![U256::one()])
+    } else if signature == event_signature(TRANSFER_SINGLE) {
+        // topics: [event, operator, from, to], data: (id, value)
+        let decoded = abi::decode(&[ParamType::Uint(256), ParamType::Uint(256)], &log.data).ok()?;
+        let mut id_and_value = decoded.into_iter().filter_map(Token::into_uint);
+        let (id, value) = (id_and_value.next()?, id_and_value.next()?);
+        ("erc1155", *log.topics.get(2)?, *log.topics.get(3)?, vec![id], vec![value])
+    } else if signature == event_signature(TRANSFER_BATCH) {
+        // topics: [event, operator, from, to], data: (ids, values)
+        let ids_and_values = [
+            ParamType::Array(Box::new(ParamType::Uint(256))),
+            ParamType::Array(Box::new(ParamType::Uint(256))),
+        ];
+        let mut decoded = abi::decode(&ids_and_values, &log.data).ok()?.into_iter();
+        let ids: Vec<U256> = decoded.next()?.into_array()?.into_iter().filter_map(Token::into_uint).collect();
+        let values: Vec<U256> = decoded.next()?.into_array()?.into_iter().filter_map(Token::into_uint).collect();
+        if ids.len() != values.len() {
+            return None;
+        }
+        ("erc1155", *log.topics.get(2)?, *log.topics.get(3)?, ids, values)
+    } else {
+        return None;
+    };
+
+    Some(EventKind::Transfer {
+        token_standard,
+        from: holder(from),
+        to: holder(to),
+        token_ids: token_ids.iter().map(|id| id.to_string()).collect(),
+        amounts: amounts.iter().map(|amount| amount.to_string()).collect(),
+    })
+}
+
+// An address topic as a lowercase, 0x-prefixed address; None for the zero address, which
+// stands for "no holder" in mints and burns.
+fn holder(topic: H256) -> Option<String> {
+    (!topic.is_zero()).then(|| format!("{:?}", Address::from(topic)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_provider_log_limits() {
+        assert!(is_log_limit_error("(code: -32005, message: query returned more than 10000 results, data: None)"));
+        assert!(is_log_limit_error("Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range"));
+        assert!(is_log_limit_error("block range is too wide"));
+        assert!(!is_log_limit_error("connection reset by peer"));
+    }
+}
@@ -0,0 +1,11 @@
+-- Full-text search over NFT names, descriptions and attribute values. Only the `value` of each
+-- attribute is indexed: trait types like 'Background' or 'Eyes' are shared by most tokens.
+-- Name matches rank above description matches, which rank above attribute matches.
+ALTER TABLE nft_metadata ADD COLUMN IF NOT EXISTS search_vector TSVECTOR
+    GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', coalesce(name, '')), 'A') ||
+        setweight(to_tsvector('english', coalesce(description, '')), 'B') ||
+        setweight(jsonb_to_tsvector('english', jsonb_path_query_array(coalesce(attributes, '[]'::jsonb), '$[*].value'), '["string"]'), 'C')
+    ) STORED;
+
+CREATE INDEX IF NOT EXISTS idx_nft_metadata_search_vector ON nft_metadata USING GIN (search_vector);
@@ -0,0 +1,16 @@
+-- Collections Table: one row per NFT contract, populated from CollectionJobs
+CREATE TABLE IF NOT EXISTS collections (
+    id SERIAL PRIMARY KEY,
+    chain TEXT NOT NULL,
+    contract_address TEXT NOT NULL,
+    name TEXT,
+    symbol TEXT,
+    token_standard TEXT, -- e.g. 'erc721', 'erc1155'
+    total_supply TEXT, -- uint256 as a decimal string, like token_id
+    first_seen_block BIGINT,
+    contract_uri TEXT,
+    contract_metadata JSONB, -- JSON document served at contract_uri
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (chain, contract_address)
+);
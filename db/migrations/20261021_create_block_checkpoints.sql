@@ -0,0 +1,6 @@
+-- Block Checkpoints Table: last block whose logs the event listener fully processed
+CREATE TABLE IF NOT EXISTS block_checkpoints (
+    chain TEXT PRIMARY KEY,
+    last_block BIGINT NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
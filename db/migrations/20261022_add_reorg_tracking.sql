@@ -0,0 +1,18 @@
+-- Reorg handling: mint jobs carry the block they came from, so rows minted in a block
+-- that is later reorganized away can be found by its hash and flagged as orphaned.
+ALTER TABLE nft_metadata ADD COLUMN IF NOT EXISTS block_number BIGINT;
+ALTER TABLE nft_metadata ADD COLUMN IF NOT EXISTS block_hash TEXT;
+ALTER TABLE nft_metadata ADD COLUMN IF NOT EXISTS orphaned BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE nft_media ADD COLUMN IF NOT EXISTS orphaned BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS idx_nft_metadata_block_hash ON nft_metadata (chain, block_hash);
+
+-- Processed Blocks Table: hash of every block the event listener produced jobs from,
+-- compared against the canonical chain on startup to detect deep reorgs
+CREATE TABLE IF NOT EXISTS processed_blocks (
+    chain TEXT NOT NULL,
+    block_number BIGINT NOT NULL,
+    block_hash TEXT NOT NULL,
+    processed_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (chain, block_number)
+);
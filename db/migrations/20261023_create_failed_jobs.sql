@@ -0,0 +1,18 @@
+-- Failed Jobs Table: jobs the metadata worker gave up on after exhausting its retries
+-- (or that can never succeed), kept for inspection and manual replay
+CREATE TABLE IF NOT EXISTS failed_jobs (
+    id SERIAL PRIMARY KEY,
+    topic TEXT NOT NULL,
+    job_key TEXT,
+    payload JSONB NOT NULL,
+    attempts INTEGER NOT NULL,
+    error_class TEXT NOT NULL,
+    last_error TEXT NOT NULL,
+    failed_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_failed_jobs_error_class ON failed_jobs (error_class);
+
+-- A job redelivered after a crash (its offset was not committed yet) can be dead-lettered
+-- twice; keep one row per job so the second write updates it instead
+CREATE UNIQUE INDEX IF NOT EXISTS idx_failed_jobs_job ON failed_jobs (topic, md5(payload::text));
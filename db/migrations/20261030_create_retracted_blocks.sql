@@ -0,0 +1,16 @@
+-- Retracted Blocks Table: block hashes the event listener reported as reorganized away.
+-- Jobs from these blocks can still arrive afterwards (retried or redelivered) and must
+-- not bring their tokens or transfers back.
+CREATE TABLE IF NOT EXISTS retracted_blocks (
+    chain TEXT NOT NULL,
+    block_hash TEXT NOT NULL,
+    block_number BIGINT NOT NULL,
+    retracted_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (chain, block_hash)
+);
+
+-- Blocks retracted before this table existed
+INSERT INTO retracted_blocks (chain, block_hash, block_number)
+SELECT DISTINCT chain, block_hash, block_number FROM nft_metadata
+WHERE orphaned AND block_hash IS NOT NULL
+ON CONFLICT DO NOTHING;
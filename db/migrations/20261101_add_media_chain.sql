@@ -0,0 +1,19 @@
+-- Media is keyed by chain like the metadata it belongs to, so the same contract address
+-- and token id on two chains no longer share (and overwrite) one media row.
+ALTER TABLE nft_media ADD COLUMN IF NOT EXISTS chain TEXT;
+
+-- Existing rows take the chain of their token; a token id indexed on several chains
+-- keeps its media on the one indexed last, and the others cache theirs again on refresh.
+UPDATE nft_media m SET chain = (
+    SELECT nm.chain FROM nft_metadata nm
+    WHERE nm.contract_address = m.contract_address AND nm.token_id = m.token_id
+    ORDER BY nm.id DESC
+    LIMIT 1
+)
+WHERE m.chain IS NULL;
+-- Media of tokens that were never indexed cannot be shown anywhere.
+DELETE FROM nft_media WHERE chain IS NULL;
+ALTER TABLE nft_media ALTER COLUMN chain SET NOT NULL;
+
+ALTER TABLE nft_media DROP CONSTRAINT IF EXISTS nft_media_contract_address_token_id_media_type_key;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_nft_media_token ON nft_media (chain, contract_address, token_id, media_type);
@@ -0,0 +1,80 @@
+// Last fully processed block per chain, so a restarted listener can replay what it missed,
+// plus the hashes of recently processed blocks for reorg detection.
+
+use sqlx::PgPool;
+
+pub async fn load(pool: &PgPool, chain: &str) -> Result<Option<u64>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT last_block FROM block_checkpoints WHERE chain = $1",
+        chain
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|r| r.last_block as u64))
+}
+
+pub async fn save(pool: &PgPool, chain: &str, last_block: u64) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO block_checkpoints (chain, last_block, updated_at)
+           VALUES ($1, $2, NOW())
+           ON CONFLICT (chain) DO UPDATE SET last_block = EXCLUDED.last_block, updated_at = NOW()"#,
+        chain,
+        last_block as i64
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Blocks older than this are assumed final and their hashes are pruned.
+const PROCESSED_BLOCK_HISTORY: u64 = 10_000;
+
+// Remembers the hashes of blocks whose logs were handled, so a later reorg of them can be detected.
+pub async fn record_blocks(pool: &PgPool, chain: &str, blocks: &[(u64, String)]) -> Result<(), sqlx::Error> {
+    let Some(newest) = blocks.iter().map(|(number, _)| *number).max() else {
+        return Ok(());
+    };
+    let numbers: Vec<i64> = blocks.iter().map(|(number, _)| *number as i64).collect();
+    let hashes: Vec<String> = blocks.iter().map(|(_, hash)| hash.clone()).collect();
+    sqlx::query!(
+        r#"INSERT INTO processed_blocks (chain, block_number, block_hash, processed_at)
+           SELECT $1, block_number, block_hash, NOW() FROM UNNEST($2::bigint[], $3::text[]) AS b(block_number, block_hash)
+           ON CONFLICT (chain, block_number) DO UPDATE SET block_hash = EXCLUDED.block_hash, processed_at = NOW()"#,
+        chain,
+        &numbers,
+        &hashes
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM processed_blocks WHERE chain = $1 AND block_number < $2",
+        chain,
+        newest.saturating_sub(PROCESSED_BLOCK_HISTORY) as i64
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Recorded (block number, hash) pairs, newest first.
+pub async fn recent_blocks(pool: &PgPool, chain: &str, limit: i64) -> Result<Vec<(u64, String)>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT block_number, block_hash FROM processed_blocks WHERE chain = $1 ORDER BY block_number DESC LIMIT $2",
+        chain,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| (r.block_number as u64, r.block_hash)).collect())
+}
+
+pub async fn forget_blocks_from(pool: &PgPool, chain: &str, from_block: u64) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM processed_blocks WHERE chain = $1 AND block_number >= $2",
+        chain,
+        from_block as i64
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
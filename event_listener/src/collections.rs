@@ -0,0 +1,15 @@
+// Collections as the metadata worker recorded them.
+
+use sqlx::PgPool;
+
+// The token standard recorded for a collection, or None when the worker has not seen it.
+pub async fn token_standard(pool: &PgPool, chain: &str, contract_address: &str) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT token_standard FROM collections WHERE chain = $1 AND contract_address = $2",
+        chain,
+        contract_address
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.and_then(|r| r.token_standard))
+}
@@ -0,0 +1,56 @@
+// Chain adapter for tests: serves the block hashes it is given and nothing else, unless a
+// test asks for `uri(id)` to revert.
+
+use async_trait::async_trait;
+use common::chain::{ChainAdapter, ChainEvent, CollectionInfo};
+use futures::stream::BoxStream;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct FakeNode {
+    hashes: HashMap<u64, String>,
+    token_uri_error: Option<&'static str>,
+}
+
+impl FakeNode {
+    // Blocks it has not been given are missing, as on a node that has not seen them yet.
+    pub fn with_blocks(blocks: &[(u64, &str)]) -> Self {
+        FakeNode { hashes: blocks.iter().map(|(number, hash)| (*number, hash.to_string())).collect(), ..Default::default() }
+    }
+
+    // Every `token_uri` call fails as a reverting ERC-1155 `uri(id)` does.
+    pub fn reverting_token_uri() -> Self {
+        FakeNode { token_uri_error: Some("execution reverted"), ..Default::default() }
+    }
+}
+
+#[async_trait]
+impl ChainAdapter for FakeNode {
+    fn chain(&self) -> &str {
+        "ethereum"
+    }
+    async fn head(&self) -> anyhow::Result<u64> {
+        Ok(self.hashes.keys().copied().max().unwrap_or(0))
+    }
+    async fn block_hash(&self, block_number: u64) -> anyhow::Result<Option<String>> {
+        Ok(self.hashes.get(&block_number).cloned())
+    }
+    async fn subscribe_events(&self) -> anyhow::Result<BoxStream<'_, ChainEvent>> {
+        anyhow::bail!("not served by FakeNode")
+    }
+    async fn events_in_range(&self, _from_block: u64, _to_block: u64) -> anyhow::Result<Vec<ChainEvent>> {
+        Ok(Vec::new())
+    }
+    async fn token_uri(&self, _contract_address: &str, _token_id: &str, _token_standard: &str) -> anyhow::Result<String> {
+        anyhow::bail!(self.token_uri_error.unwrap_or("not served by FakeNode"))
+    }
+    async fn collection_info(&self, _contract_address: &str) -> CollectionInfo {
+        CollectionInfo::default()
+    }
+    async fn enumerate_collection(&self, _contract_address: &str) -> anyhow::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+    async fn tokens_in_range(&self, _contract_address: &str, _from: &str, _to: &str) -> anyhow::Result<Option<Vec<String>>> {
+        Ok(None)
+    }
+}
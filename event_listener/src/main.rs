@@ -1,40 +1,84 @@
 // Required environment variables for Confluent Cloud:
 // KAFKA_BROKERS
 // KAFKA_TOPIC
+// KAFKA_COLLECTION_TOPIC
+// KAFKA_RETRACTION_TOPIC (optional, defaults to nft_retraction_jobs)
+// KAFKA_REFRESH_TOPIC (optional, defaults to nft_refresh_jobs)
+// KAFKA_TRANSFER_TOPIC (optional, defaults to nft_transfer_jobs)
+// KAFKA_DEAD_LETTER_TOPIC (optional, defaults to nft_jobs_dead_letter)
+// KAFKA_MAX_IN_FLIGHT (optional, pending deliveries per chain, defaults to 1000)
+// KAFKA_MAX_RETRIES (optional, defaults to 5)
+// DEAD_LETTER_FILE (optional, defaults to dead_letter_jobs.jsonl)
 // KAFKA_USERNAME
 // KAFKA_PASSWORD
-// ETHEREUM_WS_URL
+// DATABASE_URL (block checkpoints, recorded collections, emitted ERC-1155 URIs)
+// LOG_CHUNK_SIZE (optional, blocks per eth_getLogs call when catching up, at least 1, defaults to 100;
+//   the calls match every ERC-20 Transfer too, and windows the provider rejects as too big are halved)
+// CONFIRMATIONS (optional, blocks a log must be buried under before it is handled, defaults to 12)
+// CHAINS (optional, comma-separated EVM chains to listen to, defaults to ethereum)
+//
+// Per chain, with the chain name upper-cased (e.g. ETHEREUM_WS_URL, POLYGON_WS_URL):
+// <CHAIN>_WS_URL
+// <CHAIN>_CHAIN_ID (optional for ethereum, polygon, base and arbitrum)
+// <CHAIN>_START_BLOCK (optional, where to start when no checkpoint exists, defaults to the chain head)
+// <CHAIN>_CONFIRMATIONS (optional, overrides CONFIRMATIONS)
 
-use ethers::prelude::*;
-use ethers::providers::{Provider, Ws};
-use ethers::types::{Filter, H256, U256};
+mod checkpoint;
+mod collections;
+#[cfg(test)]
+mod fake_node;
+mod producer;
+mod reorg;
+mod token_uris;
+
+use common::chain::{ChainAdapter, ChainEvent, EventKind};
+use common::evm::{self, EvmAdapter, EvmChainConfig};
+use common::{CollectionJob, NftMintJob, RefreshJob, TransferJob};
+use futures::future::join_all;
+use futures::StreamExt;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
-use std::sync::Arc;
-use common::NftMintJob;
 use rdkafka::config::ClientConfig;
-use rdkafka::producer::{FutureProducer, FutureRecord};
-use serde_json;
-use std::time::Duration;
-use anyhow;
-
-// ERC-721 ABI fragment for tokenURI
-abigen!(ERC721, r#"[
-    function tokenURI(uint256 tokenId) external view returns (string)
-]"#);
+use rdkafka::producer::FutureProducer;
+use std::time::{Duration, Instant};
+use sqlx::PgPool;
+use producer::{JobProducer, ProducerConfig};
 
-// ERC-1155 ABI fragment (no tokenURI standard, but some contracts implement uri(uint256))
-abigen!(ERC1155, r#"[
-    function uri(uint256 id) external view returns (string)
-]"#);
+const INITIAL_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+// How long a session must stay up before a disconnect restarts the backoff from scratch.
+const HEALTHY_UPTIME: Duration = Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Load Ethereum node URL and Kafka brokers from env
-    let ws_url = env::var("ETHEREUM_WS_URL").expect("ETHEREUM_WS_URL must be set");
+    // Load Kafka brokers and the chains to listen to from env
     let kafka_brokers = env::var("KAFKA_BROKERS").expect("KAFKA_BROKERS must be set");
     let kafka_topic = env::var("KAFKA_TOPIC").unwrap_or_else(|_| "nft_mint_jobs".to_string());
+    let collection_topic = env::var("KAFKA_COLLECTION_TOPIC").unwrap_or_else(|_| "nft_collection_jobs".to_string());
+    let retraction_topic = env::var("KAFKA_RETRACTION_TOPIC").unwrap_or_else(|_| "nft_retraction_jobs".to_string());
+    let refresh_topic = env::var("KAFKA_REFRESH_TOPIC").unwrap_or_else(|_| "nft_refresh_jobs".to_string());
+    let transfer_topic = env::var("KAFKA_TRANSFER_TOPIC").unwrap_or_else(|_| "nft_transfer_jobs".to_string());
     let kafka_username = env::var("KAFKA_USERNAME").expect("KAFKA_USERNAME must be set for Confluent Cloud");
     let kafka_password = env::var("KAFKA_PASSWORD").expect("KAFKA_PASSWORD must be set for Confluent Cloud");
+    let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let log_chunk_size: u64 = env_or("LOG_CHUNK_SIZE", 100);
+    assert!(log_chunk_size >= 1, "LOG_CHUNK_SIZE must be at least 1");
+    // With no delivery slots, the first job would wait for one forever.
+    let max_in_flight: usize = env_or("KAFKA_MAX_IN_FLIGHT", 1000);
+    assert!(max_in_flight >= 1, "KAFKA_MAX_IN_FLIGHT must be at least 1");
+    let confirmations: u64 = env_or("CONFIRMATIONS", 12);
+    let chains = env::var("CHAINS").unwrap_or_else(|_| "ethereum".to_string());
+
+    let sessions: Vec<SessionConfig> = chains
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .map(|name| session_config(name, log_chunk_size, confirmations, &retraction_topic))
+        .collect();
+
+    let pool = PgPool::connect(&db_url).await?;
 
     // Set up Kafka producer with Confluent Cloud SASL/PLAIN authentication
     let producer: FutureProducer = ClientConfig::new()
@@ -43,137 +87,551 @@ async fn main() -> anyhow::Result<()> {
         .set("sasl.mechanism", "PLAIN")
         .set("sasl.username", &kafka_username)
         .set("sasl.password", &kafka_password)
+        .set("enable.idempotence", "true")
         .create()
         .expect("Failed to create Kafka producer");
 
-    let producer = Arc::new(producer);
-
-    let provider = Provider::<Ws>::connect(ws_url.clone()).await?;
-    let provider2 = Arc::new(Provider::<Ws>::connect(ws_url).await?); // For contract calls
-
-    // ERC-721 and ERC-1155 event names (not hashes)
-    let filter = Filter::new()
-        .event("Transfer(address,address,uint256)")
-        .event("TransferSingle(address,address,address,uint256,uint256)")
-        .event("TransferBatch(address,address,address,uint256[],uint256[])");
-    let mut stream = provider.subscribe_logs(&filter).await?;
-
-    println!("Listening for NFT Transfer events (ERC-721 & ERC-1155)...");
-    while let Some(log) = stream.next().await {
-        // ERC-721 Transfer
-        if let Some(event_sig) = log.topics.get(0) {
-            if *event_sig == H256::from_slice(&ethers::utils::keccak256("Transfer(address,address,uint256)")) {
-                // topics: [event, from, to, tokenId]
-                if let Some(from) = log.topics.get(1) {
-                    if *from == H256::zero() {
-                        // Mint detected
-                        let contract_address = log.address;
-                        let token_id = log.topics.get(3)
-                            .map(|h| U256::from_big_endian(h.as_bytes()).to_string())
-                            .unwrap_or_else(|| "unknown".to_string());
-                        // Try to fetch tokenURI
-                        let erc721 = ERC721::new(contract_address, provider2.clone());
-                        let token_id_u256 = U256::from_dec_str(&token_id).unwrap_or(U256::zero());
-                        let metadata_uri = match erc721.token_uri(token_id_u256).call().await {
-                            Ok(uri) => Some(uri),
-                            Err(_) => None,
-                        };
-                        let job = NftMintJob {
-                            contract_address: format!("0x{:x}", contract_address),
-                            token_id: token_id.clone(),
-                            chain: "ethereum".to_string(),
-                            metadata_uri,
-                        };
-                        println!("[ERC-721] Detected NFT mint: {:?}", job);
-                        produce_job(producer.clone(), kafka_topic.clone(), job).await;
+    let producer_config = ProducerConfig {
+        max_in_flight,
+        max_retries: env_or("KAFKA_MAX_RETRIES", 5),
+        initial_backoff: Duration::from_millis(500),
+        dead_letter_topic: env::var("KAFKA_DEAD_LETTER_TOPIC").unwrap_or_else(|_| "nft_jobs_dead_letter".to_string()),
+        dead_letter_file: env::var("DEAD_LETTER_FILE").unwrap_or_else(|_| "dead_letter_jobs.jsonl".to_string()),
+    };
+    // Every chain has its own delivery slots, so a checkpoint flush on one chain never
+    // waits for, or holds up, another chain's jobs.
+    let producers: Vec<JobProducer> = sessions.iter().map(|_| JobProducer::new(producer.clone(), producer_config.clone())).collect();
+
+    // One independently supervised session per chain, all sharing the Kafka client and pool.
+    let listeners = sessions.iter().zip(&producers).map(|(session, producer)| {
+        let mut handler = EventHandler {
+            producer: producer.clone(),
+            kafka_topic: kafka_topic.clone(),
+            collection_topic: collection_topic.clone(),
+            refresh_topic: refresh_topic.clone(),
+            transfer_topic: transfer_topic.clone(),
+            pool: pool.clone(),
+            seen_collections: HashSet::new(),
+            last_handled: None,
+        };
+        let pool = &pool;
+        async move { supervise(session, &mut handler, pool).await }
+    });
+    tokio::select! {
+        _ = join_all(listeners) => {}
+        _ = tokio::signal::ctrl_c() => println!("Shutting down..."),
+    }
+    println!("Waiting for pending Kafka deliveries...");
+    join_all(producers.iter().map(JobProducer::flush)).await;
+    Ok(())
+}
+
+struct SessionConfig {
+    chain: EvmChainConfig,
+    log_chunk_size: u64,
+    start_block: Option<u64>,
+    confirmations: u64,
+    retraction_topic: String,
+}
+
+// Reads the <CHAIN>_* variables for one configured chain.
+fn session_config(name: String, log_chunk_size: u64, default_confirmations: u64, retraction_topic: &str) -> SessionConfig {
+    let prefix = name.to_uppercase();
+    let rpc_url = env::var(format!("{prefix}_WS_URL")).unwrap_or_else(|_| panic!("{prefix}_WS_URL must be set"));
+    let chain_id = match env::var(format!("{prefix}_CHAIN_ID")) {
+        Ok(id) => id.parse().unwrap_or_else(|_| panic!("{prefix}_CHAIN_ID must be a valid number")),
+        Err(_) => evm::known_chain_id(&name).unwrap_or_else(|| panic!("{prefix}_CHAIN_ID must be set for chain '{name}'")),
+    };
+    SessionConfig {
+        start_block: env::var(format!("{prefix}_START_BLOCK")).ok().map(|b| b.parse().unwrap_or_else(|_| panic!("{prefix}_START_BLOCK must be a valid number"))),
+        confirmations: env_or(&format!("{prefix}_CONFIRMATIONS"), default_confirmations),
+        chain: EvmChainConfig { name, chain_id, rpc_url },
+        log_chunk_size,
+        retraction_topic: retraction_topic.to_string(),
+    }
+}
+
+// Runs listener sessions forever, reconnecting with exponential backoff whenever the
+// subscription ends or the node stops answering. Every session resumes from the last
+// checkpoint, so logs emitted while disconnected are replayed.
+async fn supervise(session: &SessionConfig, handler: &mut EventHandler, pool: &PgPool) {
+    let chain = &session.chain.name;
+    let mut reconnect_delay = INITIAL_RECONNECT_DELAY;
+    loop {
+        match run_session(session, handler, pool, &mut reconnect_delay).await {
+            Ok(()) => eprintln!("[WARN] {} log subscription stream ended", chain),
+            Err(e) => eprintln!("[ERROR] {} listener session failed: {}", chain, e),
+        }
+        eprintln!("Reconnecting to {} in {:?}...", chain, reconnect_delay);
+        tokio::time::sleep(reconnect_delay).await;
+        reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+async fn run_session(
+    session: &SessionConfig,
+    handler: &mut EventHandler,
+    pool: &PgPool,
+    reconnect_delay: &mut Duration,
+) -> anyhow::Result<()> {
+    let adapter = EvmAdapter::connect(session.chain.clone()).await?;
+    let chain = adapter.chain();
+
+    // Subscribe before reading the chain head so nothing falls between the fetched
+    // range and the live stream; events seen twice are deduplicated while pending.
+    let mut stream = adapter.subscribe_events().await?;
+    let connected_at = Instant::now();
+    let head = adapter.head().await?;
+
+    // Retract handled blocks that were reorganized away while we were not looking, and
+    // replay from the first of them so their replacements are picked up.
+    let mut last_processed = checkpoint::load(pool, chain).await?;
+    if let Some(orphaned_from) = reorg::reconcile(&adapter, pool, &handler.producer, &session.retraction_topic).await? {
+        last_processed = Some(orphaned_from - 1);
+        checkpoint::save(pool, chain, orphaned_from - 1).await?;
+        handler.last_handled = None;
+    }
+
+    let mut pending = reorg::PendingEvents::new(session.confirmations);
+    let confirmed_head = pending.confirmed_head(head);
+    let resume_from = match last_processed {
+        Some(last_processed) => last_processed + 1,
+        None => session.start_block.unwrap_or(confirmed_head + 1),
+    };
+    if resume_from <= confirmed_head {
+        println!("Replaying {} blocks {}..={} missed since the last checkpoint", chain, resume_from, confirmed_head);
+        replay_range(&adapter, handler, pool, resume_from, confirmed_head, session.log_chunk_size).await?;
+    }
+    // Events from blocks that are not confirmed yet wait alongside the live ones.
+    let unconfirmed_from = resume_from.max(confirmed_head + 1);
+    if unconfirmed_from <= head {
+        for event in adapter.events_in_range(unconfirmed_from, head).await? {
+            pending.insert(event);
+        }
+    }
+
+    // Newest block seen, highest block whose events have been handled, and highest block
+    // whose logs are all pending or handled (the subscription delivers a block's logs
+    // before the next block's).
+    let mut latest_block = head;
+    let mut last_confirmed = confirmed_head;
+    let mut complete_upto = head;
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    heartbeat.tick().await; // the first tick completes immediately
+
+    println!("Listening for {} NFT transfers (ERC-721 & ERC-1155) and metadata changes, {} confirmations...", chain, session.confirmations);
+    loop {
+        // A node that accepts the subscription and drops it right away keeps backing off;
+        // only a session that has been up for a while restarts the backoff.
+        if connected_at.elapsed() >= HEALTHY_UPTIME {
+            *reconnect_delay = INITIAL_RECONNECT_DELAY;
+        }
+        tokio::select! {
+            next = stream.next() => {
+                let Some(event) = next else {
+                    return Ok(());
+                };
+                if event.removed {
+                    if !pending.remove(&event) && event.block_number <= last_confirmed {
+                        // Reconnecting runs the reconciliation above, which retracts the block.
+                        anyhow::bail!("Reorg reached block {}, which was already handled", event.block_number);
                     }
+                    println!("[REORG] Dropped unconfirmed {} event from block {}", chain, event.block_number);
+                    continue;
                 }
-            }
-            // ERC-1155 TransferSingle
-            else if *event_sig == H256::from_slice(&ethers::utils::keccak256("TransferSingle(address,address,address,uint256,uint256)")) {
-                // topics: [event, operator, from, to, id, value]
-                if let Some(from) = log.topics.get(2) {
-                    if *from == H256::zero() {
-                        // Mint detected
-                        let contract_address = log.address;
-                        // token_id is in data (4th topic)
-                        let token_id = log.topics.get(4)
-                            .map(|h| U256::from_big_endian(h.as_bytes()).to_string())
-                            .unwrap_or_else(|| "unknown".to_string());
-                        // Try to fetch uri (not standard, so usually None)
-                        let erc1155 = ERC1155::new(contract_address, provider2.clone());
-                        let token_id_u256 = U256::from_dec_str(&token_id).unwrap_or(U256::zero());
-                        let metadata_uri = match erc1155.uri(token_id_u256).call().await {
-                            Ok(uri) => Some(uri),
-                            Err(_) => None,
-                        };
-                        let job = NftMintJob {
-                            contract_address: format!("0x{:x}", contract_address),
-                            token_id: token_id.clone(),
-                            chain: "ethereum".to_string(),
-                            metadata_uri,
-                        };
-                        println!("[ERC-1155] Detected NFT mint: {:?}", job);
-                        produce_job(producer.clone(), kafka_topic.clone(), job).await;
-                    }
+                if event.block_number <= last_confirmed {
+                    continue; // already handled by the replay
+                }
+                let block = event.block_number;
+                complete_upto = complete_upto.max(block - 1);
+                pending.insert(event);
+                if block > latest_block {
+                    latest_block = block;
+                    last_confirmed = confirm_pending(&adapter, &mut pending, handler, pool, latest_block, complete_upto, session.log_chunk_size).await?;
+                    complete_upto = complete_upto.max(last_confirmed);
                 }
             }
-            // ERC-1155 TransferBatch
-            else if *event_sig == H256::from_slice(&ethers::utils::keccak256("TransferBatch(address,address,address,uint256[],uint256[])")) {
-                // topics: [event, operator, from, to], data: token ids and values (encoded)
-                if let Some(from) = log.topics.get(2) {
-                    if *from == H256::zero() {
-                        let contract_address = log.address;
-                        // Decode token ids from data (first 32 bytes is offset, then array)
-                        if let Ok(decoded) = ethers::abi::decode(&[ethers::abi::ParamType::Array(Box::new(ethers::abi::ParamType::Uint(256)))], &log.data.0[0..]) {
-                            if let Some(ids) = decoded.get(0).and_then(|v| v.clone().into_array()) {
-                                for id in ids {
-                                    let token_id = id.into_uint().unwrap_or(U256::zero()).to_string();
-                                    let erc1155 = ERC1155::new(contract_address, provider2.clone());
-                                    let token_id_u256 = U256::from_dec_str(&token_id).unwrap_or(U256::zero());
-                                    let metadata_uri = match erc1155.uri(token_id_u256).call().await {
-                                        Ok(uri) => Some(uri),
-                                        Err(_) => None,
-                                    };
-                                    let job = NftMintJob {
-                                        contract_address: format!("0x{:x}", contract_address),
-                                        token_id: token_id.clone(),
-                                        chain: "ethereum".to_string(),
-                                        metadata_uri,
-                                    };
-                                    println!("[ERC-1155 Batch] Detected NFT mint: {:?}", job);
-                                    produce_job(producer.clone(), kafka_topic.clone(), job.clone()).await;
-                                }
-                            }
-                        }
-                    }
+            _ = heartbeat.tick() => {
+                // A half-open socket never ends the stream, so probe the connection. The
+                // polled head also confirms pending events on chains where they are rare.
+                let head = match tokio::time::timeout(HEARTBEAT_TIMEOUT, adapter.head()).await {
+                    Ok(Ok(head)) => head,
+                    Ok(Err(e)) => anyhow::bail!("{} provider heartbeat failed: {}", chain, e),
+                    Err(_) => anyhow::bail!("{} provider heartbeat timed out", chain),
+                };
+                if head > latest_block {
+                    latest_block = head;
+                    last_confirmed = confirm_pending(&adapter, &mut pending, handler, pool, latest_block, complete_upto, session.log_chunk_size).await?;
+                    complete_upto = complete_upto.max(last_confirmed);
                 }
             }
         }
     }
+}
+
+// Handles every pending block that is now confirmed, skipping events whose block was
+// reorganized away in the meantime, then checkpoints the confirmed head once the jobs
+// are delivered. Only confirmed blocks past `complete_upto`, which the caller keeps at or
+// above the last confirmed block, are fetched from the node: the subscription has not
+// finished delivering them, as when the heartbeat's head runs ahead of a quiet or lagging
+// subscription.
+async fn confirm_pending(
+    adapter: &dyn ChainAdapter,
+    pending: &mut reorg::PendingEvents,
+    handler: &mut EventHandler,
+    pool: &PgPool,
+    latest_block: u64,
+    complete_upto: u64,
+    chunk_size: u64,
+) -> anyhow::Result<u64> {
+    let chain = adapter.chain();
+    let confirmed = pending.confirmed_head(latest_block);
+    let mut window_start = complete_upto + 1;
+    while window_start <= confirmed {
+        let window_end = (window_start + chunk_size - 1).min(confirmed);
+        for event in adapter.events_in_range(window_start, window_end).await? {
+            pending.insert(event);
+        }
+        window_start = window_end + 1;
+    }
+    for (block, mut events) in pending.take_confirmed(latest_block) {
+        // Taken events are lost on error, but the checkpoint has not moved past them yet.
+        let Some(canonical) = adapter.block_hash(block).await? else {
+            anyhow::bail!("{} block {} is not available from the node", chain, block);
+        };
+        events.retain(|event| event.block_hash == canonical);
+        if events.is_empty() {
+            continue;
+        }
+        events.sort_by_key(|event| event.log_index);
+        checkpoint::record_blocks(pool, chain, &[(block, canonical)]).await?;
+        handler.handle_events(adapter, &events).await;
+    }
+    handler.producer.flush().await;
+    checkpoint::save(pool, chain, confirmed).await?;
+    Ok(confirmed)
+}
+
+// Fetches historical events in `chunk_size`-block windows (providers cap eth_getLogs
+// ranges) and checkpoints each window once its jobs are delivered, so a crash replays
+// the window instead of losing it.
+async fn replay_range(
+    adapter: &dyn ChainAdapter,
+    handler: &mut EventHandler,
+    pool: &PgPool,
+    from: u64,
+    to: u64,
+    chunk_size: u64,
+) -> anyhow::Result<()> {
+    let chain = adapter.chain();
+    let mut window_start = from;
+    while window_start <= to {
+        let window_end = (window_start + chunk_size - 1).min(to);
+        let events = adapter.events_in_range(window_start, window_end).await?;
+        println!("Replaying {} {} events from blocks {}..={}", events.len(), chain, window_start, window_end);
+        let blocks: BTreeMap<u64, String> = events.iter().map(|event| (event.block_number, event.block_hash.clone())).collect();
+        checkpoint::record_blocks(pool, chain, &blocks.into_iter().collect::<Vec<_>>()).await?;
+        handler.handle_events(adapter, &events).await;
+        handler.producer.flush().await;
+        checkpoint::save(pool, chain, window_end).await?;
+        window_start = window_end + 1;
+    }
     Ok(())
 }
 
-async fn produce_job(producer: Arc<FutureProducer>, topic: String, job: NftMintJob) {
-    match serde_json::to_string(&job) {
-        Ok(payload) => {
-            let key = job.contract_address.clone();
-            // Leak the memory so the reference is 'static for the async task
-            let topic = Box::leak(topic.into_boxed_str());
-            let payload = Box::leak(payload.into_boxed_str());
-            let key = Box::leak(key.into_boxed_str());
-            let record = FutureRecord::to(topic)
-                .payload(payload)
-                .key(key);
-            let producer = Arc::clone(&producer);
-            tokio::spawn(async move {
-                let produce_future = producer.send(record, Duration::from_secs(0));
-                match produce_future.await {
-                    Ok((partition, offset)) => println!("Kafka delivery success: partition={partition}, offset={offset}"),
-                    Err((e, _msg)) => eprintln!("Kafka delivery error: {e:?}"),
+// Turns events from one chain into jobs.
+struct EventHandler {
+    producer: JobProducer,
+    kafka_topic: String,
+    collection_topic: String,
+    refresh_topic: String,
+    transfer_topic: String,
+    pool: PgPool,
+    // Contracts already reported to the collection topic by this process
+    seen_collections: HashSet<String>,
+    // (block, log index) of the newest log handled, to skip logs replayed after a reconnect
+    last_handled: Option<(u64, u64)>,
+}
+
+// (block, contract, token id) of an event concerning one token.
+type TokenInBlock<'a> = (u64, &'a str, &'a str);
+
+// What the other logs of the same block say about a token.
+#[derive(Default)]
+struct BlockContext<'a> {
+    // Last ERC-1155 URI emitted for each token
+    emitted_uris: HashMap<TokenInBlock<'a>, &'a str>,
+    minted: HashSet<TokenInBlock<'a>>,
+}
+
+impl<'a> BlockContext<'a> {
+    fn new(events: &'a [ChainEvent]) -> Self {
+        let mut context = BlockContext::default();
+        for event in events {
+            let contract = event.contract_address.as_str();
+            match &event.kind {
+                EventKind::Transfer { from: None, token_ids, .. } => {
+                    context.minted.extend(token_ids.iter().map(|id| (event.block_number, contract, id.as_str())));
+                }
+                EventKind::UriChanged { token_id, uri } => {
+                    context.emitted_uris.insert((event.block_number, contract, token_id.as_str()), uri.as_str());
+                }
+                EventKind::Transfer { .. } | EventKind::MetadataUpdate { .. } => {}
+            }
+        }
+        context
+    }
+}
+
+impl EventHandler {
+    // Handles the events of one block, or of one replay window, in log order.
+    async fn handle_events(&mut self, adapter: &dyn ChainAdapter, events: &[ChainEvent]) {
+        let context = BlockContext::new(events);
+        for event in events {
+            let position = (event.block_number, event.log_index);
+            if self.last_handled.is_some_and(|last| position <= last) {
+                continue;
+            }
+            self.last_handled = Some(position);
+
+            match &event.kind {
+                EventKind::Transfer { token_standard, from, to, token_ids, amounts } => {
+                    let job = TransferJob {
+                        chain: adapter.chain().to_string(),
+                        contract_address: event.contract_address.clone(),
+                        token_standard: token_standard.to_string(),
+                        from_address: from.clone(),
+                        to_address: to.clone(),
+                        token_ids: token_ids.clone(),
+                        amounts: amounts.clone(),
+                        tx_hash: event.tx_hash.clone(),
+                        block_number: event.block_number,
+                        block_hash: event.block_hash.clone(),
+                        log_index: event.log_index,
+                    };
+                    if to.is_none() {
+                        println!(
+                            "[{}] Detected {} burn of {} tokens {:?}",
+                            adapter.chain(), token_standard, job.contract_address, job.token_ids
+                        );
+                    }
+                    // Every transfer goes to the worker, which keeps the history, balances
+                    // and burned supply.
+                    self.producer.send_job(&self.transfer_topic, &job.contract_address, &job).await;
+                    if from.is_none() {
+                        self.handle_mint(adapter, event, token_ids, token_standard, &context).await;
+                    }
+                }
+                EventKind::MetadataUpdate { from_token_id, to_token_id } => {
+                    self.handle_metadata_update(adapter, event, from_token_id, to_token_id).await
                 }
-            });
+                EventKind::UriChanged { token_id, uri } => self.handle_uri_changed(adapter, event, token_id, uri, &context).await,
+            }
+        }
+    }
+
+    async fn handle_mint(
+        &mut self,
+        adapter: &dyn ChainAdapter,
+        event: &ChainEvent,
+        token_ids: &[String],
+        token_standard: &str,
+        context: &BlockContext<'_>,
+    ) {
+        for token_id in token_ids {
+            let metadata_uri = mint_uri(&self.pool, adapter, event, token_id, token_standard, context).await;
+            let job = NftMintJob {
+                contract_address: event.contract_address.clone(),
+                token_id: token_id.clone(),
+                chain: adapter.chain().to_string(),
+                metadata_uri,
+                block_number: Some(event.block_number),
+                block_hash: Some(event.block_hash.clone()),
+            };
+            println!("[{}] Detected {} mint: {:?}", adapter.chain(), token_standard, job);
+            self.producer.send_job(&self.kafka_topic, &job.contract_address, &job).await;
+        }
+        self.announce_collection(adapter, event, token_standard).await;
+    }
+
+    // EIP-4906 updates usually follow a reveal or a base URI change, so the token URI is
+    // read again, as the collection's recorded standard defines it; the worker falls back
+    // to the stored one when the call fails or the collection is not recorded yet. A range
+    // too wide to expand goes to the worker as one job. The worker skips ids it never
+    // indexed, such as ones in the range that were not minted.
+    async fn handle_metadata_update(&mut self, adapter: &dyn ChainAdapter, event: &ChainEvent, from_token_id: &str, to_token_id: &str) {
+        let token_ids = match adapter.tokens_in_range(&event.contract_address, from_token_id, to_token_id).await {
+            Ok(Some(token_ids)) => token_ids,
+            Ok(None) => {
+                let job = RefreshJob {
+                    chain: adapter.chain().to_string(),
+                    contract_address: event.contract_address.clone(),
+                    token_id: from_token_id.to_string(),
+                    metadata_uri: None,
+                    to_token_id: Some(to_token_id.to_string()),
+                };
+                println!("[{}] Metadata update for {} tokens {}..={}, refreshing the indexed ones", adapter.chain(), job.contract_address, from_token_id, to_token_id);
+                self.producer.send_job(&self.refresh_topic, &job.contract_address, &job).await;
+                return;
+            }
+            Err(e) => {
+                eprintln!(
+                    "[ERROR] Could not resolve {} metadata update of {} tokens {}..={}: {}",
+                    adapter.chain(), event.contract_address, from_token_id, to_token_id, e
+                );
+                return;
+            }
+        };
+        let token_standard = match collections::token_standard(&self.pool, adapter.chain(), &event.contract_address).await {
+            Ok(token_standard) => token_standard,
+            Err(e) => {
+                eprintln!("[ERROR] Could not look up the token standard of {}: {}", event.contract_address, e);
+                None
+            }
+        };
+        println!(
+            "[{}] Metadata update for {} tokens {}..={}, refreshing {}",
+            adapter.chain(), event.contract_address, from_token_id, to_token_id, token_ids.len()
+        );
+        for token_id in token_ids {
+            let metadata_uri = match &token_standard {
+                Some(token_standard) => adapter.token_uri(&event.contract_address, &token_id, token_standard).await.ok(),
+                None => None,
+            };
+            let job = RefreshJob {
+                chain: adapter.chain().to_string(),
+                contract_address: event.contract_address.clone(),
+                token_id,
+                metadata_uri,
+                to_token_id: None,
+            };
+            self.producer.send_job(&self.refresh_topic, &job.contract_address, &job).await;
+        }
+    }
+
+    // Every emitted URI is remembered for mints in later blocks. A URI set after the token
+    // was minted also goes to the worker, which fetches the metadata from it and stores it
+    // as the token's URI, indexing the token if its mint found no URI. URIs emitted with
+    // the mint travel in the mint job.
+    async fn handle_uri_changed(
+        &mut self,
+        adapter: &dyn ChainAdapter,
+        event: &ChainEvent,
+        token_id: &str,
+        uri: &str,
+        context: &BlockContext<'_>,
+    ) {
+        if let Err(e) = token_uris::record(&self.pool, adapter.chain(), &event.contract_address, token_id, uri, event.block_number, event.log_index).await {
+            eprintln!("[ERROR] Could not record the URI of {} token {}: {}", event.contract_address, token_id, e);
         }
-        Err(e) => eprintln!("Failed to serialize job: {e}"),
+        if context.minted.contains(&(event.block_number, event.contract_address.as_str(), token_id)) {
+            return;
+        }
+        let job = RefreshJob {
+            chain: adapter.chain().to_string(),
+            contract_address: event.contract_address.clone(),
+            token_id: token_id.to_string(),
+            metadata_uri: Some(uri.to_string()),
+            to_token_id: None,
+        };
+        println!("[{}] URI changed: {:?}", adapter.chain(), job);
+        self.producer.send_job(&self.refresh_topic, &job.contract_address, &job).await;
+    }
+
+    // Reads contract-level details for a collection the first time one of its mints is seen.
+    async fn announce_collection(&mut self, adapter: &dyn ChainAdapter, event: &ChainEvent, token_standard: &str) {
+        if !self.seen_collections.insert(event.contract_address.clone()) {
+            return;
+        }
+        let info = adapter.collection_info(&event.contract_address).await;
+        let collection = CollectionJob {
+            contract_address: event.contract_address.clone(),
+            chain: adapter.chain().to_string(),
+            name: info.name,
+            symbol: info.symbol,
+            token_standard: Some(token_standard.to_string()),
+            total_supply: info.total_supply,
+            first_seen_block: Some(event.block_number),
+            contract_uri: info.contract_uri,
+        };
+        println!("New {} collection: {:?}", token_standard, collection);
+        self.producer.send_job(&self.collection_topic, &collection.contract_address, &collection).await;
+    }
+}
+
+// The metadata URI of a minted token. ERC-1155 contracts often emit a URI event next to
+// the mint, and per EIP-1155 that value is the token's URI, so it is used in place of
+// calling `uri(id)` (which reverts on some contracts). When the call fails, the URI an
+// event set in an earlier block is used.
+async fn mint_uri(
+    pool: &PgPool,
+    adapter: &dyn ChainAdapter,
+    event: &ChainEvent,
+    token_id: &str,
+    token_standard: &str,
+    context: &BlockContext<'_>,
+) -> Option<String> {
+    if let Some(uri) = context.emitted_uris.get(&(event.block_number, event.contract_address.as_str(), token_id)) {
+        return Some(uri.to_string());
+    }
+    if let Ok(uri) = adapter.token_uri(&event.contract_address, token_id, token_standard).await {
+        return Some(uri);
+    }
+    match token_uris::load(pool, adapter.chain(), &event.contract_address, token_id).await {
+        Ok(uri) => uri,
+        Err(e) => {
+            eprintln!("[ERROR] Could not look up the emitted URI of {} token {}: {}", event.contract_address, token_id, e);
+            None
+        }
+    }
+}
+
+// Parses an optional numeric env var, falling back to `default` when unset.
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| panic!("{name} must be a valid number")),
+        Err(_) => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake_node::FakeNode;
+
+    fn mint_in(block_number: u64) -> ChainEvent {
+        ChainEvent {
+            contract_address: "0xabc".to_string(),
+            kind: EventKind::Transfer {
+                token_standard: "erc1155",
+                from: None,
+                to: Some("0xowner".to_string()),
+                token_ids: vec!["7".to_string()],
+                amounts: vec!["1".to_string()],
+            },
+            block_number,
+            block_hash: format!("0x{block_number}"),
+            log_index: 0,
+            tx_hash: "0xtx".to_string(),
+            removed: false,
+        }
+    }
+
+    // setURI in block 100, mint in block 105.
+    #[sqlx::test(migrations = "../db/migrations")]
+    async fn mint_uses_uri_emitted_in_an_earlier_block(pool: PgPool) {
+        token_uris::record(&pool, "ethereum", "0xabc", "7", "ipfs://new/7.json", 100, 3).await.unwrap();
+        // A replay of an older event does not bring its URI back.
+        token_uris::record(&pool, "ethereum", "0xabc", "7", "ipfs://old/7.json", 90, 0).await.unwrap();
+
+        let event = mint_in(105);
+        let uri = mint_uri(&pool, &FakeNode::reverting_token_uri(), &event, "7", "erc1155", &BlockContext::default()).await;
+        assert_eq!(uri.as_deref(), Some("ipfs://new/7.json"));
+    }
+
+    #[sqlx::test(migrations = "../db/migrations")]
+    async fn mint_without_any_uri_has_none(pool: PgPool) {
+        let event = mint_in(105);
+        assert_eq!(mint_uri(&pool, &FakeNode::reverting_token_uri(), &event, "7", "erc1155", &BlockContext::default()).await, None);
     }
 }
@@ -0,0 +1,159 @@
+// Kafka producer used for every job the listener emits.
+//
+// Each record owns its topic, key and payload, at most `max_in_flight` deliveries are
+// pending at once (callers wait for a free slot, which slows the log loop down instead
+// of buffering without bound), retriable errors are retried with exponential backoff,
+// and records that still fail are handed to the dead-letter sink.
+
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::types::RDKafkaErrorCode;
+use rdkafka::error::KafkaError;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+
+// How long a send may wait for space in librdkafka's local queue before it counts as
+// a (retriable) QueueFull failure.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct ProducerConfig {
+    pub max_in_flight: usize,
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub dead_letter_topic: String,
+    // Last-resort sink, used when the dead-letter topic itself cannot be written to.
+    pub dead_letter_file: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DeadLetter<'a> {
+    topic: &'a str,
+    key: &'a str,
+    payload: &'a str,
+    error: String,
+}
+
+#[derive(Clone)]
+pub struct JobProducer {
+    producer: FutureProducer,
+    in_flight: Arc<Semaphore>,
+    config: Arc<ProducerConfig>,
+}
+
+impl JobProducer {
+    pub fn new(producer: FutureProducer, config: ProducerConfig) -> Self {
+        JobProducer {
+            producer,
+            in_flight: Arc::new(Semaphore::new(config.max_in_flight)),
+            config: Arc::new(config),
+        }
+    }
+
+    // Serializes `job` and queues it for delivery. Returns once the record has an
+    // in-flight slot; delivery, retries and dead-lettering continue in the background.
+    pub async fn send_job<T: Serialize>(&self, topic: &str, key: &str, job: &T) {
+        let payload = match serde_json::to_string(job) {
+            Ok(payload) => payload,
+            Err(e) => {
+                eprintln!("Failed to serialize job: {e}");
+                return;
+            }
+        };
+        let permit = Arc::clone(&self.in_flight)
+            .acquire_owned()
+            .await
+            .expect("in-flight semaphore is never closed");
+        let this = self.clone();
+        let topic = topic.to_string();
+        let key = key.to_string();
+        tokio::spawn(async move {
+            this.deliver(&topic, &key, &payload).await;
+            drop(permit);
+        });
+    }
+
+    // Waits until every queued record has been delivered or dead-lettered. Checkpoints
+    // are only saved after a flush, so the listener never skips past undelivered jobs.
+    pub async fn flush(&self) {
+        let all = self.config.max_in_flight as u32;
+        let _permits = self.in_flight.acquire_many(all).await.expect("in-flight semaphore is never closed");
+    }
+
+    async fn deliver(&self, topic: &str, key: &str, payload: &str) {
+        let mut backoff = self.config.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            let record = FutureRecord::to(topic).payload(payload).key(key);
+            match self.producer.send(record, QUEUE_TIMEOUT).await {
+                Ok((partition, offset)) => {
+                    println!("Kafka delivery success: topic={topic}, partition={partition}, offset={offset}");
+                    return;
+                }
+                Err((e, _msg)) if is_retriable(&e) && attempt < self.config.max_retries => {
+                    attempt += 1;
+                    eprintln!("Kafka delivery error (attempt {attempt}/{}), retrying in {backoff:?}: {e}", self.config.max_retries);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                Err((e, _msg)) => {
+                    eprintln!("Kafka delivery failed permanently for topic={topic}, key={key}: {e}");
+                    self.dead_letter(topic, key, payload, e).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn dead_letter(&self, topic: &str, key: &str, payload: &str, error: KafkaError) {
+        let error = error.to_string();
+        let headers = OwnedHeaders::new()
+            .insert(Header { key: "original_topic", value: Some(topic) })
+            .insert(Header { key: "error", value: Some(error.as_str()) });
+        let record = FutureRecord::to(&self.config.dead_letter_topic)
+            .payload(payload)
+            .key(key)
+            .headers(headers);
+        match self.producer.send(record, QUEUE_TIMEOUT).await {
+            Ok(_) => eprintln!("Job for key={key} sent to dead-letter topic {}", self.config.dead_letter_topic),
+            Err((e, _msg)) => {
+                eprintln!("Dead-letter topic unavailable ({e}), writing job to {}", self.config.dead_letter_file);
+                let line = DeadLetter { topic, key, payload, error };
+                if let Err(e) = append_json_line(&self.config.dead_letter_file, &line).await {
+                    // Nothing else to fall back to: print the job so it can be replayed by hand.
+                    eprintln!("Failed to write dead-letter file: {e}; dropped job: {payload}");
+                }
+            }
+        }
+    }
+}
+
+fn is_retriable(e: &KafkaError) -> bool {
+    matches!(
+        e.rdkafka_error_code(),
+        Some(
+            RDKafkaErrorCode::QueueFull
+                | RDKafkaErrorCode::MessageTimedOut
+                | RDKafkaErrorCode::TimedOutQueue
+                | RDKafkaErrorCode::RequestTimedOut
+                | RDKafkaErrorCode::NetworkException
+                | RDKafkaErrorCode::BrokerTransportFailure
+                | RDKafkaErrorCode::AllBrokersDown
+                | RDKafkaErrorCode::LeaderNotAvailable
+                | RDKafkaErrorCode::NotLeaderForPartition
+                | RDKafkaErrorCode::NotEnoughReplicas
+                | RDKafkaErrorCode::NotEnoughReplicasAfterAppend
+        )
+    )
+}
+
+async fn append_json_line<T: Serialize>(path: &str, value: &T) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path).await?;
+    let line = serde_json::to_string(value)? + "\n";
+    file.write_all(line.as_bytes()).await
+}
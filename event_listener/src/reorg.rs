@@ -0,0 +1,139 @@
+// Reorg handling.
+//
+// Live events wait in `PendingEvents` until their block is `depth` blocks below the newest
+// block seen, and are only handled if their block is still canonical by then, so shallow
+// reorgs never produce jobs. Deeper reorgs, which rewrite blocks that were already
+// handled, are found by comparing the recorded hashes of processed blocks with the
+// canonical chain; each orphaned block is retracted so the worker can hide its tokens.
+
+use common::chain::{ChainAdapter, ChainEvent};
+use common::RetractionJob;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+
+use crate::checkpoint;
+use crate::producer::JobProducer;
+
+// How many recorded blocks are compared with the canonical chain before giving up.
+const RECONCILE_LIMIT: i64 = 256;
+
+pub struct PendingEvents {
+    depth: u64,
+    blocks: BTreeMap<u64, Vec<ChainEvent>>,
+}
+
+impl PendingEvents {
+    pub fn new(depth: u64) -> Self {
+        PendingEvents { depth, blocks: BTreeMap::new() }
+    }
+
+    // Queues an event unless it is already pending (the same log can arrive both from the
+    // startup range query and from the subscription).
+    pub fn insert(&mut self, event: ChainEvent) {
+        let events = self.blocks.entry(event.block_number).or_default();
+        if !events.iter().any(|pending| same_log(pending, &event)) {
+            events.push(event);
+        }
+    }
+
+    // Drops an event the node reported as removed. Returns false when it was not pending,
+    // i.e. its block had already been confirmed and handled.
+    pub fn remove(&mut self, removed: &ChainEvent) -> bool {
+        let Some(events) = self.blocks.get_mut(&removed.block_number) else {
+            return false;
+        };
+        let before = events.len();
+        events.retain(|event| !same_log(event, removed));
+        let found = events.len() != before;
+        if events.is_empty() {
+            self.blocks.remove(&removed.block_number);
+        }
+        found
+    }
+
+    // Highest block that counts as confirmed once `head` has been seen.
+    pub fn confirmed_head(&self, head: u64) -> u64 {
+        head.saturating_sub(self.depth)
+    }
+
+    // Removes and returns the blocks that are now confirmed, oldest first.
+    pub fn take_confirmed(&mut self, head: u64) -> Vec<(u64, Vec<ChainEvent>)> {
+        let unconfirmed = self.blocks.split_off(&(self.confirmed_head(head) + 1));
+        std::mem::replace(&mut self.blocks, unconfirmed).into_iter().collect()
+    }
+}
+
+fn same_log(a: &ChainEvent, b: &ChainEvent) -> bool {
+    a.block_hash == b.block_hash && a.log_index == b.log_index
+}
+
+// Walks the recorded blocks from the newest down until one still matches the canonical
+// chain, retracting every block that does not. Returns the lowest orphaned block, from
+// which the caller must replay.
+pub async fn reconcile(
+    adapter: &dyn ChainAdapter,
+    pool: &PgPool,
+    producer: &JobProducer,
+    retraction_topic: &str,
+) -> anyhow::Result<Option<u64>> {
+    let chain = adapter.chain();
+    let recorded = checkpoint::recent_blocks(pool, chain, RECONCILE_LIMIT).await?;
+    let orphaned = orphaned_blocks(adapter, recorded).await?;
+    for (block_number, block_hash) in &orphaned {
+        println!("[REORG] {} block {} ({}) is no longer canonical, retracting its jobs", chain, block_number, block_hash);
+        let job = RetractionJob { chain: chain.to_string(), block_number: *block_number, block_hash: block_hash.clone() };
+        producer.send_job(retraction_topic, &job.block_hash, &job).await;
+    }
+    let orphaned_from = orphaned.last().map(|(block_number, _)| *block_number);
+    if let Some(from_block) = orphaned_from {
+        producer.flush().await; // keep the blocks until their retractions are delivered
+        checkpoint::forget_blocks_from(pool, chain, from_block).await?;
+    }
+    Ok(orphaned_from)
+}
+
+// The recorded blocks (newest first) that the node reports a different hash for, down to
+// the first one that still matches. A block the node does not have yet (a lagging node
+// behind a load balancer) proves nothing, so the walk fails before anything is retracted
+// and the session retries.
+async fn orphaned_blocks(adapter: &dyn ChainAdapter, recorded: Vec<(u64, String)>) -> anyhow::Result<Vec<(u64, String)>> {
+    let mut orphaned = Vec::new();
+    for (block_number, block_hash) in recorded {
+        let Some(canonical) = adapter.block_hash(block_number).await? else {
+            anyhow::bail!("{} block {} is not available from the node", adapter.chain(), block_number);
+        };
+        if canonical == block_hash {
+            break; // every earlier block is an ancestor of this one
+        }
+        orphaned.push((block_number, block_hash));
+    }
+    Ok(orphaned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_node::FakeNode;
+
+    fn node(blocks: &[(u64, &str)]) -> FakeNode {
+        FakeNode::with_blocks(blocks)
+    }
+
+    fn recorded(blocks: &[(u64, &str)]) -> Vec<(u64, String)> {
+        blocks.iter().map(|(number, hash)| (*number, hash.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn lagging_node_retracts_nothing() {
+        let lagging = node(&[(100, "0xa"), (101, "0xb")]);
+        let result = orphaned_blocks(&lagging, recorded(&[(102, "0xc"), (101, "0xb"), (100, "0xa")])).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn different_hash_is_retracted() {
+        let reorged = node(&[(100, "0xa"), (101, "0xb2"), (102, "0xc2")]);
+        let orphaned = orphaned_blocks(&reorged, recorded(&[(102, "0xc"), (101, "0xb"), (100, "0xa")])).await.unwrap();
+        assert_eq!(orphaned, recorded(&[(102, "0xc"), (101, "0xb")]));
+    }
+}
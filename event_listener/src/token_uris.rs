@@ -0,0 +1,39 @@
+// URIs set by ERC-1155 `URI` events, kept so a token minted in a later block can use the
+// one set before its mint when `uri(id)` is unavailable.
+
+use sqlx::PgPool;
+
+// Keeps the URI of the newest event per token; a replayed older event does not overwrite it.
+pub async fn record(pool: &PgPool, chain: &str, contract_address: &str, token_id: &str, uri: &str, block_number: u64, log_index: u64) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO emitted_token_uris (chain, contract_address, token_id, uri, block_number, log_index, recorded_at)
+           VALUES ($1, $2, $3, $4, $5, $6, NOW())
+           ON CONFLICT (chain, contract_address, token_id) DO UPDATE SET
+               uri = EXCLUDED.uri,
+               block_number = EXCLUDED.block_number,
+               log_index = EXCLUDED.log_index,
+               recorded_at = NOW()
+           WHERE (EXCLUDED.block_number, EXCLUDED.log_index) >= (emitted_token_uris.block_number, emitted_token_uris.log_index)"#,
+        chain,
+        contract_address,
+        token_id,
+        uri,
+        block_number as i64,
+        log_index as i64
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// The last URI emitted for a token, or None when no `URI` event named it.
+pub async fn load(pool: &PgPool, chain: &str, contract_address: &str, token_id: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT uri FROM emitted_token_uris WHERE chain = $1 AND contract_address = $2 AND token_id = $3",
+        chain,
+        contract_address,
+        token_id
+    )
+    .fetch_optional(pool)
+    .await
+}
@@ -0,0 +1,14 @@
+-- Media Renditions Table: resized WebP copies of cached images, keyed by the content hash
+-- of the original, so tokens sharing an image share its renditions
+CREATE TABLE IF NOT EXISTS media_renditions (
+    id SERIAL PRIMARY KEY,
+    content_hash TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    mime_type TEXT NOT NULL,
+    cached_url TEXT NOT NULL,
+    storage_backend TEXT NOT NULL,
+    byte_size BIGINT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE (content_hash, width)
+);
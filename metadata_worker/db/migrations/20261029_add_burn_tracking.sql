@@ -0,0 +1,47 @@
+-- Token Supply Table: amounts minted and burned per token, from the transfers table.
+-- An ERC-721 token is burned when its latest burn comes after its latest mint, so a token
+-- burned and then minted again is live; the positions are (block_number, log_index,
+-- batch_index) of the transfers, which compare in order. A token minted before transfers
+-- were recorded has no recorded mint, so its burn still burns it. An ERC-1155 token is
+-- burned once everything minted of it has been burned; without a recorded mint its
+-- remaining supply is unknown, so it is never flagged.
+CREATE TABLE IF NOT EXISTS token_supply (
+    chain TEXT NOT NULL,
+    contract_address TEXT NOT NULL,
+    token_id TEXT NOT NULL,
+    token_standard TEXT NOT NULL,
+    minted NUMERIC(78, 0) NOT NULL DEFAULT 0,
+    burned_amount NUMERIC(78, 0) NOT NULL DEFAULT 0,
+    last_mint BIGINT[],
+    last_burn BIGINT[],
+    burned BOOLEAN GENERATED ALWAYS AS (
+        CASE WHEN token_standard = 'erc721'
+             THEN last_burn IS NOT NULL AND (last_mint IS NULL OR last_burn > last_mint)
+             ELSE burned_amount > 0 AND burned_amount >= minted AND minted > 0
+        END
+    ) STORED,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (chain, contract_address, token_id)
+);
+
+ALTER TABLE nft_metadata ADD COLUMN IF NOT EXISTS burned BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS idx_nft_metadata_not_burned ON nft_metadata (id DESC) WHERE NOT burned AND NOT orphaned;
+
+-- Mints and burns recorded before this table existed
+INSERT INTO token_supply (chain, contract_address, token_id, token_standard, minted, burned_amount, last_mint, last_burn, updated_at)
+SELECT t.chain, t.contract_address, t.token_id, COALESCE(c.token_standard, 'erc1155'),
+       COALESCE(SUM(t.amount) FILTER (WHERE t.from_address IS NULL), 0),
+       COALESCE(SUM(t.amount) FILTER (WHERE t.to_address IS NULL), 0),
+       MAX(ARRAY[t.block_number, t.log_index, t.batch_index]) FILTER (WHERE t.from_address IS NULL),
+       MAX(ARRAY[t.block_number, t.log_index, t.batch_index]) FILTER (WHERE t.to_address IS NULL),
+       NOW()
+FROM transfers t
+LEFT JOIN collections c ON c.chain = t.chain AND c.contract_address = t.contract_address
+WHERE t.from_address IS NULL OR t.to_address IS NULL
+GROUP BY t.chain, t.contract_address, t.token_id, c.token_standard
+ON CONFLICT (chain, contract_address, token_id) DO NOTHING;
+
+UPDATE nft_metadata nm SET burned = TRUE
+FROM token_supply s
+WHERE s.chain = nm.chain AND s.contract_address = nm.contract_address AND s.token_id = nm.token_id AND s.burned;
@@ -9,9 +9,17 @@
 //   - attributes (jsonb)
 //   - raw_metadata (jsonb)
 //   - created_at (timestamp)
+//   - search_vector (tsvector, generated) -- full-text index over name, description, attribute values
+//   - block_number (bigint) -- block of the mint log, null for backfilled tokens
+//   - block_hash (text)
+//   - orphaned (boolean) -- set when the mint block was reorganized away
+//   - token_uri (text) -- URI the metadata was fetched from, reused by refreshes
+//   - updated_at (timestamp) -- last time the metadata changed
+//   - burned (boolean) -- copied from token_supply.burned
 //
 // Table: nft_media
 //   - id (serial primary key)
+//   - chain (text)
 //   - contract_address (text)
 //   - token_id (text)
 //   - media_type (text) -- e.g. 'image', 'animation'
@@ -19,8 +27,123 @@
 //   - cached_url (text)
 //   - storage_backend (text) -- e.g. 'local', 's3'
 //   - created_at (timestamp)
+//   - orphaned (boolean)
+//   - content_hash (text) -- SHA-256 of the file, also its storage key
+//   - byte_size (bigint)
+//   - mime_type (text)
+//
+// Table: collections
+//   - id (serial primary key)
+//   - chain (text)
+//   - contract_address (text)
+//   - name (text)
+//   - symbol (text)
+//   - token_standard (text) -- e.g. 'erc721', 'erc1155'
+//   - total_supply (text)
+//   - first_seen_block (bigint)
+//   - contract_uri (text)
+//   - contract_metadata (jsonb)
+//   - created_at (timestamp)
+//   - updated_at (timestamp)
+//
+// Table: block_checkpoints (written by the event listener)
+//   - chain (text primary key)
+//   - last_block (bigint)
+//   - updated_at (timestamp)
+//
+// Table: processed_blocks (written by the event listener)
+//   - chain (text)
+//   - block_number (bigint)
+//   - block_hash (text)
+//   - processed_at (timestamp)
+//
+// Table: failed_jobs (written by the metadata worker)
+//   - id (serial primary key)
+//   - topic (text) -- topic the job was originally published to
+//   - job_key (text)
+//   - payload (jsonb)
+//   - attempts (integer)
+//   - error_class (text) -- e.g. 'network', 'http_status', 'database'
+//   - last_error (text)
+//   - failed_at (timestamp)
+//
+// Table: metadata_versions -- every distinct metadata document a token has had
+//   - id (serial primary key)
+//   - chain (text)
+//   - contract_address (text)
+//   - token_id (text)
+//   - token_uri (text)
+//   - raw_metadata (jsonb)
+//   - recorded_at (timestamp)
+//
+// Table: metadata_refresh_requests (written by the api, for rate limiting)
+//   - chain (text)
+//   - contract_address (text)
+//   - token_id (text)
+//   - requested_at (timestamp)
+//
+// Table: media_renditions (written by the metadata worker)
+//   - id (serial primary key)
+//   - content_hash (text) -- nft_media.content_hash of the original image
+//   - width (integer) -- widths the original is no wider than point at the original itself
+//   - height (integer)
+//   - mime_type (text)
+//   - cached_url (text)
+//   - storage_backend (text)
+//   - byte_size (bigint)
+//   - created_at (timestamp)
+//
+// Table: transfers (written by the metadata worker) -- one row per token moved
+//   - id (bigserial primary key)
+//   - chain (text)
+//   - contract_address (text)
+//   - token_id (text)
+//   - from_address (text) -- null for mints
+//   - to_address (text) -- null for burns
+//   - amount (numeric) -- always 1 for ERC-721
+//   - tx_hash (text)
+//   - block_number (bigint)
+//   - block_hash (text)
+//   - log_index (bigint)
+//   - batch_index (integer) -- position of the token within an ERC-1155 batch
+//   - created_at (timestamp)
+//
+// Table: token_balances (written by the metadata worker)
+//   - chain (text)
+//   - contract_address (text)
+//   - token_id (text)
+//   - owner (text)
+//   - balance (numeric) -- current holders have a positive balance
+//   - updated_at (timestamp)
+//
+// Table: token_supply (written by the metadata worker)
+//   - chain (text)
+//   - contract_address (text)
+//   - token_id (text)
+//   - token_standard (text)
+//   - minted (numeric) -- amounts recorded in transfers
+//   - burned_amount (numeric)
+//   - last_mint (bigint[]) -- (block_number, log_index, batch_index) of the latest mint
+//   - last_burn (bigint[])
+//   - burned (boolean, generated) -- ERC-721: burned after the latest mint; ERC-1155: everything minted burned
+//   - updated_at (timestamp)
+//
+// Table: retracted_blocks (written by the metadata worker)
+//   - chain (text)
+//   - block_hash (text) -- block reorganized away; rows from it stay orphaned
+//   - block_number (bigint)
+//   - retracted_at (timestamp)
+//
+// Table: emitted_token_uris (written by the event listener)
+//   - chain (text)
+//   - contract_address (text)
+//   - token_id (text)
+//   - uri (text) -- last URI an ERC-1155 `URI` event set for the token
+//   - block_number (bigint)
+//   - log_index (bigint)
+//   - recorded_at (timestamp)
 
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgPool};
 use serde_json::Value;
 
 #[derive(serde::Serialize)]
@@ -32,48 +155,592 @@ pub struct NftMetadata {
     pub description: Option<String>,
     pub attributes: Option<Value>,
     pub raw_metadata: Value,
+    pub block_number: Option<i64>,
+    pub block_hash: Option<String>,
+    pub token_uri: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataChange {
+    Inserted,
+    Updated,
+    Unchanged,
+    // The token's block was retracted; the row is stored but stays orphaned.
+    Retracted,
 }
 
 pub struct NftMedia {
+    pub chain: String,
     pub contract_address: String,
     pub token_id: String,
     pub media_type: String,
     pub original_url: String,
     pub cached_url: String,
     pub storage_backend: String,
+    pub content_hash: String,
+    pub byte_size: i64,
+    pub mime_type: Option<String>,
 }
 
-pub async fn insert_nft_metadata(pool: &PgPool, meta: &NftMetadata) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"INSERT INTO nft_metadata (contract_address, token_id, chain, name, description, attributes, raw_metadata, created_at)
-           VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
-           ON CONFLICT (contract_address, token_id, chain) DO NOTHING"#,
+pub struct MediaRendition {
+    pub content_hash: String,
+    pub width: i32,
+    pub height: i32,
+    pub mime_type: String,
+    pub cached_url: String,
+    pub storage_backend: String,
+    pub byte_size: i64,
+}
+
+// One transfer log; `token_ids` and `amounts` (decimal strings) line up.
+pub struct Transfer {
+    pub chain: String,
+    pub contract_address: String,
+    pub token_standard: String,
+    pub from_address: Option<String>,
+    pub to_address: Option<String>,
+    pub token_ids: Vec<String>,
+    pub amounts: Vec<String>,
+    pub tx_hash: String,
+    pub block_number: i64,
+    pub block_hash: String,
+    pub log_index: i64,
+}
+
+pub struct FailedJob {
+    pub topic: String,
+    pub job_key: Option<String>,
+    pub payload: Value,
+    pub attempts: i32,
+    pub error_class: String,
+    pub last_error: String,
+}
+
+pub struct Collection {
+    pub chain: String,
+    pub contract_address: String,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub token_standard: Option<String>,
+    pub total_supply: Option<String>,
+    pub first_seen_block: Option<i64>,
+    pub contract_uri: Option<String>,
+    pub contract_metadata: Option<Value>,
+}
+
+// Inserts or updates a token's metadata and reports whether the document changed. A new
+// document is also appended to metadata_versions. A token seen again (replayed after a
+// reorg, re-minted in the replacement block, refreshed) is no longer orphaned, unless its
+// block is in retracted_blocks: a mint job from a retracted block that is retried or
+// redelivered after the retraction keeps the token orphaned. It holds the block's lock,
+// so it cannot interleave with the retraction itself. Block details and the token
+// URI are only overwritten when the new values are known. A new row starts out burned
+// when its burn was recorded first.
+pub async fn insert_nft_metadata(pool: &PgPool, meta: &NftMetadata) -> Result<MetadataChange, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    if let Some(block_hash) = &meta.block_hash {
+        lock_block(&mut tx, &meta.chain, block_hash).await?;
+    }
+    let row = sqlx::query!(
+        r#"WITH previous AS (
+               SELECT raw_metadata, block_hash FROM nft_metadata
+               WHERE contract_address = $1 AND token_id = $2 AND chain = $3
+           ), retracted AS (
+               SELECT EXISTS (
+                   SELECT 1 FROM retracted_blocks
+                   WHERE chain = $3 AND block_hash = COALESCE($9, (SELECT block_hash FROM previous))
+               ) AS retracted
+           ), upserted AS (
+               INSERT INTO nft_metadata (contract_address, token_id, chain, name, description, attributes, raw_metadata, block_number, block_hash, token_uri, burned, orphaned, created_at, updated_at)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10,
+                       COALESCE((SELECT burned FROM token_supply WHERE chain = $3 AND contract_address = $1 AND token_id = $2), FALSE),
+                       (SELECT retracted FROM retracted),
+                       NOW(), NOW())
+               ON CONFLICT (contract_address, token_id, chain) DO UPDATE SET
+                   name = EXCLUDED.name,
+                   description = EXCLUDED.description,
+                   attributes = EXCLUDED.attributes,
+                   raw_metadata = EXCLUDED.raw_metadata,
+                   block_number = COALESCE(EXCLUDED.block_number, nft_metadata.block_number),
+                   block_hash = COALESCE(EXCLUDED.block_hash, nft_metadata.block_hash),
+                   token_uri = COALESCE(EXCLUDED.token_uri, nft_metadata.token_uri),
+                   orphaned = (SELECT retracted FROM retracted),
+                   updated_at = CASE WHEN nft_metadata.raw_metadata = EXCLUDED.raw_metadata
+                                     THEN nft_metadata.updated_at ELSE NOW() END
+           ), version AS (
+               INSERT INTO metadata_versions (chain, contract_address, token_id, token_uri, raw_metadata, recorded_at)
+               SELECT $3, $1, $2, $10, $7, NOW()
+               WHERE NOT EXISTS (SELECT 1 FROM previous WHERE raw_metadata = $7)
+           )
+           SELECT
+               EXISTS (SELECT 1 FROM previous) AS "existed!",
+               EXISTS (SELECT 1 FROM previous WHERE raw_metadata = $7) AS "unchanged!",
+               (SELECT retracted FROM retracted) AS "retracted!""#,
         meta.contract_address,
         meta.token_id,
         meta.chain,
         meta.name,
         meta.description,
         meta.attributes.clone(),
-        meta.raw_metadata.clone()
+        meta.raw_metadata.clone(),
+        meta.block_number,
+        meta.block_hash,
+        meta.token_uri
     )
-    .execute(pool)
+    .fetch_one(&mut *tx)
     .await?;
-    Ok(())
+    tx.commit().await?;
+    Ok(match (row.existed, row.unchanged) {
+        _ if row.retracted => MetadataChange::Retracted,
+        (false, _) => MetadataChange::Inserted,
+        (true, false) => MetadataChange::Updated,
+        (true, true) => MetadataChange::Unchanged,
+    })
+}
+
+// The token URI stored for a token, which may be None; None for unknown or orphaned tokens.
+pub async fn find_token_uri(pool: &PgPool, chain: &str, contract_address: &str, token_id: &str) -> Result<Option<Option<String>>, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT token_uri FROM nft_metadata WHERE chain = $1 AND contract_address = $2 AND token_id = $3 AND NOT orphaned",
+        chain,
+        contract_address,
+        token_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|row| row.token_uri))
+}
+
+// The token standard a token was minted under, e.g. 'erc721'; None for unknown tokens.
+pub async fn token_standard(pool: &PgPool, chain: &str, contract_address: &str, token_id: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        "SELECT token_standard FROM token_supply WHERE chain = $1 AND contract_address = $2 AND token_id = $3",
+        chain,
+        contract_address,
+        token_id
+    )
+    .fetch_optional(pool)
+    .await
 }
 
+// Indexed, non-orphaned tokens of a collection whose ids lie in the inclusive range, in id order.
+pub async fn token_ids_in_range(pool: &PgPool, chain: &str, contract_address: &str, from_token_id: &str, to_token_id: &str) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT token_id FROM nft_metadata
+           WHERE chain = $1 AND contract_address = $2 AND NOT orphaned
+             AND CASE WHEN token_id ~ '^[0-9]+$' THEN token_id::numeric END BETWEEN $3::text::numeric AND $4::text::numeric
+           ORDER BY token_id::numeric"#,
+        chain,
+        contract_address,
+        from_token_id,
+        to_token_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// A token seen again points at whatever file it serves now (the URL or the content may
+// have changed) and is no longer orphaned.
 pub async fn insert_nft_media(pool: &PgPool, media: &NftMedia) -> Result<(), sqlx::Error> {
     sqlx::query!(
-        r#"INSERT INTO nft_media (contract_address, token_id, media_type, original_url, cached_url, storage_backend, created_at)
-           VALUES ($1, $2, $3, $4, $5, $6, NOW())
-           ON CONFLICT (contract_address, token_id, media_type) DO NOTHING"#,
+        r#"INSERT INTO nft_media (chain, contract_address, token_id, media_type, original_url, cached_url, storage_backend, content_hash, byte_size, mime_type, created_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, NOW())
+           ON CONFLICT (chain, contract_address, token_id, media_type) DO UPDATE SET
+               original_url = EXCLUDED.original_url,
+               cached_url = EXCLUDED.cached_url,
+               storage_backend = EXCLUDED.storage_backend,
+               content_hash = EXCLUDED.content_hash,
+               byte_size = EXCLUDED.byte_size,
+               mime_type = EXCLUDED.mime_type,
+               orphaned = FALSE"#,
+        media.chain,
         media.contract_address,
         media.token_id,
         media.media_type,
         media.original_url,
         media.cached_url,
-        media.storage_backend
+        media.storage_backend,
+        media.content_hash,
+        media.byte_size,
+        media.mime_type
     )
     .execute(pool)
     .await?;
     Ok(())
 }
+
+// Collections are reported repeatedly (every listener restart, every backfill run), so
+// known values are never overwritten with NULL and the earliest first_seen_block wins.
+pub async fn upsert_collection(pool: &PgPool, collection: &Collection) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO collections (chain, contract_address, name, symbol, token_standard, total_supply, first_seen_block, contract_uri, contract_metadata, created_at, updated_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, NOW(), NOW())
+           ON CONFLICT (chain, contract_address) DO UPDATE SET
+               name = COALESCE(EXCLUDED.name, collections.name),
+               symbol = COALESCE(EXCLUDED.symbol, collections.symbol),
+               token_standard = COALESCE(EXCLUDED.token_standard, collections.token_standard),
+               total_supply = COALESCE(EXCLUDED.total_supply, collections.total_supply),
+               first_seen_block = LEAST(EXCLUDED.first_seen_block, collections.first_seen_block),
+               contract_uri = COALESCE(EXCLUDED.contract_uri, collections.contract_uri),
+               contract_metadata = COALESCE(EXCLUDED.contract_metadata, collections.contract_metadata),
+               updated_at = NOW()"#,
+        collection.chain,
+        collection.contract_address,
+        collection.name,
+        collection.symbol,
+        collection.token_standard,
+        collection.total_supply,
+        collection.first_seen_block,
+        collection.contract_uri,
+        collection.contract_metadata.clone()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Takes a block's advisory lock until the transaction ends. Retracting a block and
+// recording a job from it both hold it, so a job either lands before the retraction (and
+// is undone by it) or sees the block in retracted_blocks.
+async fn lock_block(conn: &mut PgConnection, chain: &str, block_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "SELECT 1 AS locked FROM (SELECT pg_advisory_xact_lock(hashtext($1 || ':' || $2))) l",
+        chain,
+        block_hash
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(())
+}
+
+// Records the (reorganized) block as retracted and flags every token minted in it, and
+// its media, as orphaned. Matching on the block hash leaves tokens that were already
+// re-minted in the replacement block alone, and jobs from the block that arrive later
+// find it in retracted_blocks. Returns the number of tokens.
+pub async fn orphan_block(pool: &PgPool, chain: &str, block_number: i64, block_hash: &str) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    lock_block(&mut tx, chain, block_hash).await?;
+    let row = sqlx::query!(
+        r#"WITH retracted AS (
+               INSERT INTO retracted_blocks (chain, block_hash, block_number, retracted_at)
+               VALUES ($1, $2, $3, NOW())
+               ON CONFLICT (chain, block_hash) DO NOTHING
+           ), orphaned AS (
+               UPDATE nft_metadata SET orphaned = TRUE
+               WHERE chain = $1 AND block_hash = $2
+               RETURNING contract_address, token_id
+           ), orphaned_media AS (
+               UPDATE nft_media m SET orphaned = TRUE
+               FROM orphaned o
+               WHERE m.chain = $1 AND m.contract_address = o.contract_address AND m.token_id = o.token_id
+           )
+           SELECT COUNT(*) AS "count!" FROM orphaned"#,
+        chain,
+        block_hash,
+        block_number
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(row.count as u64)
+}
+
+// Records a transfer log and moves the amounts between the balances of its holders (mints
+// and burns only touch one side). The balances change only when the log is new, so
+// replayed jobs are harmless, and logs from a retracted block are not recorded at all.
+// The worker runs a contract's transfer jobs in order; only a job waiting for a retry can
+// leave a balance negative until it succeeds. Mints and burns also update token_supply,
+// and nft_metadata.burned follows it. Returns the rows added.
+pub async fn record_transfer(pool: &PgPool, transfer: &Transfer) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    lock_block(&mut tx, &transfer.chain, &transfer.block_hash).await?;
+    let row = sqlx::query!(
+        r#"WITH inserted AS (
+               INSERT INTO transfers (chain, contract_address, token_id, from_address, to_address, amount, tx_hash, block_number, block_hash, log_index, batch_index, created_at)
+               SELECT $1, $2, t.token_id, $3, $4, t.amount::numeric, $7, $8, $9, $10, (t.position - 1)::int, NOW()
+               FROM UNNEST($5::text[], $6::text[]) WITH ORDINALITY AS t(token_id, amount, position)
+               WHERE NOT EXISTS (SELECT 1 FROM retracted_blocks WHERE chain = $1 AND block_hash = $9)
+               ON CONFLICT (chain, block_hash, log_index, batch_index) DO NOTHING
+               RETURNING token_id, from_address, to_address, amount, ARRAY[block_number, log_index, batch_index] AS position
+           ), deltas AS (
+               SELECT token_id, from_address AS owner, -amount AS delta FROM inserted WHERE from_address IS NOT NULL
+               UNION ALL
+               SELECT token_id, to_address, amount FROM inserted WHERE to_address IS NOT NULL
+           ), balances AS (
+               INSERT INTO token_balances (chain, contract_address, token_id, owner, balance, updated_at)
+               SELECT $1, $2, token_id, owner, SUM(delta), NOW() FROM deltas
+               GROUP BY token_id, owner
+               ON CONFLICT (chain, contract_address, token_id, owner) DO UPDATE SET
+                   balance = token_balances.balance + EXCLUDED.balance,
+                   updated_at = NOW()
+           ), supply AS (
+               INSERT INTO token_supply (chain, contract_address, token_id, token_standard, minted, burned_amount, last_mint, last_burn, updated_at)
+               SELECT $1, $2, token_id, $11,
+                      COALESCE(SUM(amount) FILTER (WHERE from_address IS NULL), 0),
+                      COALESCE(SUM(amount) FILTER (WHERE to_address IS NULL), 0),
+                      MAX(position) FILTER (WHERE from_address IS NULL),
+                      MAX(position) FILTER (WHERE to_address IS NULL),
+                      NOW()
+               FROM inserted
+               WHERE from_address IS NULL OR to_address IS NULL
+               GROUP BY token_id
+               ON CONFLICT (chain, contract_address, token_id) DO UPDATE SET
+                   minted = token_supply.minted + EXCLUDED.minted,
+                   burned_amount = token_supply.burned_amount + EXCLUDED.burned_amount,
+                   last_mint = GREATEST(token_supply.last_mint, EXCLUDED.last_mint),
+                   last_burn = GREATEST(token_supply.last_burn, EXCLUDED.last_burn),
+                   updated_at = NOW()
+               RETURNING token_id, burned
+           ), flagged AS (
+               UPDATE nft_metadata nm SET burned = s.burned
+               FROM supply s
+               WHERE nm.chain = $1 AND nm.contract_address = $2 AND nm.token_id = s.token_id
+           )
+           SELECT COUNT(*) AS "count!" FROM inserted"#,
+        transfer.chain,
+        transfer.contract_address,
+        transfer.from_address,
+        transfer.to_address,
+        &transfer.token_ids,
+        &transfer.amounts,
+        transfer.tx_hash,
+        transfer.block_number,
+        transfer.block_hash,
+        transfer.log_index,
+        transfer.token_standard
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(row.count as u64)
+}
+
+// Deletes the transfers of a reorganized block and takes their amounts back out of the
+// balances and supplies, whose latest mint and burn are looked up again among the
+// remaining transfers (the statement still sees the deleted rows, so the block is
+// skipped by hash). Returns the number of transfer rows removed.
+pub async fn revert_transfers(pool: &PgPool, chain: &str, block_hash: &str) -> Result<u64, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"WITH deleted AS (
+               DELETE FROM transfers WHERE chain = $1 AND block_hash = $2
+               RETURNING contract_address, token_id, from_address, to_address, amount
+           ), deltas AS (
+               SELECT contract_address, token_id, from_address AS owner, amount AS delta FROM deleted WHERE from_address IS NOT NULL
+               UNION ALL
+               SELECT contract_address, token_id, to_address, -amount FROM deleted WHERE to_address IS NOT NULL
+           ), balances AS (
+               INSERT INTO token_balances (chain, contract_address, token_id, owner, balance, updated_at)
+               SELECT $1, contract_address, token_id, owner, SUM(delta), NOW() FROM deltas
+               GROUP BY contract_address, token_id, owner
+               ON CONFLICT (chain, contract_address, token_id, owner) DO UPDATE SET
+                   balance = token_balances.balance + EXCLUDED.balance,
+                   updated_at = NOW()
+           ), supply AS (
+               UPDATE token_supply s SET
+                   minted = s.minted - d.minted,
+                   burned_amount = s.burned_amount - d.burned_amount,
+                   last_mint = (
+                       SELECT MAX(ARRAY[t.block_number, t.log_index, t.batch_index]) FROM transfers t
+                       WHERE t.chain = $1 AND t.contract_address = s.contract_address AND t.token_id = s.token_id
+                         AND t.from_address IS NULL AND t.block_hash <> $2
+                   ),
+                   last_burn = (
+                       SELECT MAX(ARRAY[t.block_number, t.log_index, t.batch_index]) FROM transfers t
+                       WHERE t.chain = $1 AND t.contract_address = s.contract_address AND t.token_id = s.token_id
+                         AND t.to_address IS NULL AND t.block_hash <> $2
+                   ),
+                   updated_at = NOW()
+               FROM (
+                   SELECT contract_address, token_id,
+                          COALESCE(SUM(amount) FILTER (WHERE from_address IS NULL), 0) AS minted,
+                          COALESCE(SUM(amount) FILTER (WHERE to_address IS NULL), 0) AS burned_amount
+                   FROM deleted
+                   WHERE from_address IS NULL OR to_address IS NULL
+                   GROUP BY contract_address, token_id
+               ) d
+               WHERE s.chain = $1 AND s.contract_address = d.contract_address AND s.token_id = d.token_id
+               RETURNING s.contract_address, s.token_id, s.burned
+           ), flagged AS (
+               UPDATE nft_metadata nm SET burned = s.burned
+               FROM supply s
+               WHERE nm.chain = $1 AND nm.contract_address = s.contract_address AND nm.token_id = s.token_id
+           )
+           SELECT COUNT(*) AS "count!" FROM deleted"#,
+        chain,
+        block_hash
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.count as u64)
+}
+
+// The same job failing again (e.g. redelivered before its offset was committed) updates
+// its existing row.
+pub async fn insert_failed_job(pool: &PgPool, job: &FailedJob) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO failed_jobs (topic, job_key, payload, attempts, error_class, last_error, failed_at)
+           VALUES ($1, $2, $3, $4, $5, $6, NOW())
+           ON CONFLICT (topic, md5(payload::text)) DO UPDATE SET
+               attempts = GREATEST(EXCLUDED.attempts, failed_jobs.attempts),
+               error_class = EXCLUDED.error_class,
+               last_error = EXCLUDED.last_error,
+               failed_at = NOW()"#,
+        job.topic,
+        job.job_key,
+        job.payload.clone(),
+        job.attempts,
+        job.error_class,
+        job.last_error
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn insert_media_rendition(pool: &PgPool, rendition: &MediaRendition) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"INSERT INTO media_renditions (content_hash, width, height, mime_type, cached_url, storage_backend, byte_size, created_at)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+           ON CONFLICT (content_hash, width) DO UPDATE SET
+               height = EXCLUDED.height,
+               mime_type = EXCLUDED.mime_type,
+               cached_url = EXCLUDED.cached_url,
+               storage_backend = EXCLUDED.storage_backend,
+               byte_size = EXCLUDED.byte_size"#,
+        rendition.content_hash,
+        rendition.width,
+        rendition.height,
+        rendition.mime_type,
+        rendition.cached_url,
+        rendition.storage_backend,
+        rendition.byte_size
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Widths already rendered for an original into the given storage backend.
+pub async fn media_rendition_widths(pool: &PgPool, content_hash: &str, storage_backend: &str) -> Result<Vec<i32>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT width FROM media_renditions WHERE content_hash = $1 AND storage_backend = $2",
+        content_hash,
+        storage_backend
+    )
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(|row| row.width).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minted_in(block_hash: &str) -> NftMetadata {
+        NftMetadata {
+            contract_address: "0xabc".to_string(),
+            token_id: "1".to_string(),
+            chain: "ethereum".to_string(),
+            name: Some("Token 1".to_string()),
+            description: None,
+            attributes: None,
+            raw_metadata: serde_json::json!({ "name": "Token 1" }),
+            block_number: Some(100),
+            block_hash: Some(block_hash.to_string()),
+            token_uri: Some("ipfs://token/1".to_string()),
+        }
+    }
+
+    async fn is_orphaned(pool: &PgPool) -> bool {
+        sqlx::query_scalar!("SELECT orphaned FROM nft_metadata WHERE chain = 'ethereum' AND contract_address = '0xabc' AND token_id = '1'")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    // The mint job is retried after its block's retraction was handled.
+    #[sqlx::test(migrations = "./migrations")]
+    async fn retraction_before_mint_keeps_token_orphaned(pool: PgPool) {
+        orphan_block(&pool, "ethereum", 100, "0xdead").await.unwrap();
+
+        let change = insert_nft_metadata(&pool, &minted_in("0xdead")).await.unwrap();
+        assert_eq!(change, MetadataChange::Retracted);
+        assert!(is_orphaned(&pool).await);
+
+        // A refresh carries no block hash and must not revive it either.
+        let mut refreshed = minted_in("0xdead");
+        refreshed.block_number = None;
+        refreshed.block_hash = None;
+        assert_eq!(insert_nft_metadata(&pool, &refreshed).await.unwrap(), MetadataChange::Retracted);
+        assert!(is_orphaned(&pool).await);
+
+        // Re-minted in the replacement block.
+        let change = insert_nft_metadata(&pool, &minted_in("0xbeef")).await.unwrap();
+        assert_eq!(change, MetadataChange::Unchanged);
+        assert!(!is_orphaned(&pool).await);
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn retraction_after_mint_orphans_token(pool: PgPool) {
+        assert_eq!(insert_nft_metadata(&pool, &minted_in("0xdead")).await.unwrap(), MetadataChange::Inserted);
+        assert_eq!(orphan_block(&pool, "ethereum", 100, "0xdead").await.unwrap(), 1);
+        assert!(is_orphaned(&pool).await);
+    }
+
+    fn erc721_transfer(from: Option<&str>, to: Option<&str>, block_number: i64, block_hash: &str) -> Transfer {
+        Transfer {
+            chain: "ethereum".to_string(),
+            contract_address: "0xabc".to_string(),
+            token_standard: "erc721".to_string(),
+            from_address: from.map(str::to_string),
+            to_address: to.map(str::to_string),
+            token_ids: vec!["1".to_string()],
+            amounts: vec!["1".to_string()],
+            tx_hash: "0xtx".to_string(),
+            block_number,
+            block_hash: block_hash.to_string(),
+            log_index: 0,
+        }
+    }
+
+    // The burned flag of token_supply and the copy of it on nft_metadata.
+    async fn is_burned(pool: &PgPool) -> (bool, bool) {
+        sqlx::query!(
+            r#"SELECT s.burned AS "supply!", nm.burned AS "metadata!"
+               FROM token_supply s
+               JOIN nft_metadata nm ON nm.chain = s.chain AND nm.contract_address = s.contract_address AND nm.token_id = s.token_id
+               WHERE s.chain = 'ethereum' AND s.contract_address = '0xabc' AND s.token_id = '1'"#
+        )
+        .fetch_one(pool)
+        .await
+        .map(|row| (row.supply, row.metadata))
+        .unwrap()
+    }
+
+    #[sqlx::test(migrations = "./migrations")]
+    async fn transfer_from_retracted_block_is_not_recorded(pool: PgPool) {
+        let mint = erc721_transfer(None, Some("0xowner"), 100, "0xdead");
+        orphan_block(&pool, "ethereum", 100, "0xdead").await.unwrap();
+        assert_eq!(record_transfer(&pool, &mint).await.unwrap(), 0);
+
+        let balances = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM token_balances"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(balances, 0);
+    }
+
+    // Minted before transfers were recorded, burned, then minted again.
+    #[sqlx::test(migrations = "./migrations")]
+    async fn erc721_minted_after_its_burn_is_not_burned(pool: PgPool) {
+        insert_nft_metadata(&pool, &minted_in("0xmint")).await.unwrap();
+        record_transfer(&pool, &erc721_transfer(Some("0xowner"), None, 100, "0xburn")).await.unwrap();
+        assert_eq!(is_burned(&pool).await, (true, true));
+
+        record_transfer(&pool, &erc721_transfer(None, Some("0xowner"), 200, "0xremint")).await.unwrap();
+        assert_eq!(is_burned(&pool).await, (false, false));
+
+        revert_transfers(&pool, "ethereum", "0xremint").await.unwrap();
+        assert_eq!(is_burned(&pool).await, (true, true));
+    }
+}
@@ -0,0 +1,80 @@
+// `data:` URIs (RFC 2397), which on-chain collections return from tokenURI
+// (`data:application/json;base64,...`) and use for generated images
+// (`data:image/svg+xml;base64,...`, `data:image/svg+xml;utf8,<svg ...>`).
+
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use percent_encoding::percent_decode_str;
+
+// Contracts are not consistent about padding, so accept it either way.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DataUri {
+    pub mime_type: String,
+    pub bytes: Vec<u8>,
+}
+
+// Decodes a `data:` URI, or returns None for any other URI.
+pub fn decode(uri: &str) -> Option<Result<DataUri, String>> {
+    let rest = uri.trim().strip_prefix("data:")?;
+    let Some((header, data)) = rest.split_once(',') else {
+        return Some(Err("data URI has no ',' separator".to_string()));
+    };
+    let mut params = header.split(';');
+    let mime_type = match params.next().map(str::trim) {
+        Some(mime_type) if !mime_type.is_empty() => mime_type.to_ascii_lowercase(),
+        _ => "text/plain".to_string(),
+    };
+    let is_base64 = params.any(|param| param.trim().eq_ignore_ascii_case("base64"));
+    // Percent-encoding is allowed in both forms; raw (unencoded) JSON and SVG are common
+    // too, and decode unchanged.
+    let data: Vec<u8> = percent_decode_str(data).collect();
+    let bytes = if is_base64 {
+        let data: Vec<u8> = data.into_iter().filter(|b| !b.is_ascii_whitespace()).collect();
+        match BASE64.decode(data) {
+            Ok(bytes) => bytes,
+            Err(e) => return Some(Err(format!("invalid base64 in data URI: {}", e))),
+        }
+    } else {
+        data
+    };
+    Some(Ok(DataUri { mime_type, bytes }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(uri: &str) -> DataUri {
+        decode(uri).expect("is a data URI").expect("decodes")
+    }
+
+    #[test]
+    fn decodes_base64_json() {
+        let uri = "data:application/json;base64,eyJuYW1lIjoiIzEifQ==";
+        assert_eq!(decoded(uri), DataUri { mime_type: "application/json".into(), bytes: br##"{"name":"#1"}"##.to_vec() });
+        // Unpadded
+        assert_eq!(decoded("data:application/json;base64,eyJuYW1lIjoiIzEifQ").bytes, br##"{"name":"#1"}"##);
+    }
+
+    #[test]
+    fn decodes_percent_encoded_and_raw_svg() {
+        let svg = b"<svg xmlns=\"http://www.w3.org/2000/svg\"></svg>".to_vec();
+        let encoded = "data:image/svg+xml;charset=utf-8,%3Csvg%20xmlns%3D%22http%3A%2F%2Fwww.w3.org%2F2000%2Fsvg%22%3E%3C%2Fsvg%3E";
+        assert_eq!(decoded(encoded), DataUri { mime_type: "image/svg+xml".into(), bytes: svg.clone() });
+        assert_eq!(decoded("data:image/svg+xml;utf8,<svg xmlns=\"http://www.w3.org/2000/svg\"></svg>").bytes, svg);
+    }
+
+    #[test]
+    fn rejects_other_uris_and_garbage() {
+        assert!(decode("ipfs://QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG").is_none());
+        assert!(decode("data:application/json;base64").unwrap().is_err());
+        assert!(decode("data:application/json;base64,***").unwrap().is_err());
+        assert_eq!(decoded("data:,hello").mime_type, "text/plain");
+    }
+}
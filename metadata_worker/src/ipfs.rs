@@ -0,0 +1,166 @@
+// IPFS content resolution.
+//
+// Token URIs point at IPFS content in many shapes: `ipfs://<cid>/path`, the broken
+// `ipfs://ipfs/<cid>`, bare CIDs, and URLs of whatever gateway the creator happened to
+// use (`https://gateway.pinata.cloud/ipfs/<cid>`, `https://<cid>.ipfs.dweb.link/`). All
+// of them are reduced to a CID and a path, which are then fetched from our own ordered
+// list of gateways. A gateway that times out or answers 5xx is skipped for a cooldown
+// that doubles with each consecutive failure, and the next one is tried.
+
+use reqwest::{Client, Response, StatusCode};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::retry::{ErrorClass, JobError};
+
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BASE_COOLDOWN: Duration = Duration::from_secs(30);
+const MAX_COOLDOWN: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpfsPath {
+    pub cid: String,
+    pub path: String, // empty, or starting with '/'
+}
+
+// Extracts the CID and path from any IPFS reference, or None for non-IPFS URIs.
+pub fn parse_ipfs_uri(uri: &str) -> Option<IpfsPath> {
+    let uri = uri.trim();
+    if let Some(rest) = uri.strip_prefix("ipfs://") {
+        let rest = rest.trim_start_matches('/');
+        return split_cid(rest.strip_prefix("ipfs/").unwrap_or(rest));
+    }
+    if let Some(rest) = uri.strip_prefix("https://").or_else(|| uri.strip_prefix("http://")) {
+        let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        // Path gateway: https://<any gateway>/ipfs/<cid>/path
+        if let Some(after) = path.strip_prefix("/ipfs/") {
+            return split_cid(after);
+        }
+        // Subdomain gateway: https://<cid>.ipfs.<gateway>/path
+        let (label, domain) = host.split_once('.')?;
+        if domain.starts_with("ipfs.") && is_cid(label) {
+            return Some(IpfsPath { cid: label.to_string(), path: path.to_string() });
+        }
+        return None;
+    }
+    split_cid(uri.strip_prefix("/ipfs/").unwrap_or(uri))
+}
+
+fn split_cid(s: &str) -> Option<IpfsPath> {
+    let (cid, path) = s.split_at(s.find(['/', '?', '#']).unwrap_or(s.len()));
+    is_cid(cid).then(|| IpfsPath { cid: cid.to_string(), path: path.to_string() })
+}
+
+// CIDv0 is a base58btc sha256 multihash ("Qm" + 44 chars). CIDv1 strings carry a multibase
+// prefix; gateways and wallets use base32 ('b', e.g. "bafy..."), base36 ('k') or base58btc ('z').
+fn is_cid(s: &str) -> bool {
+    let in_alphabet = |alphabet: &dyn Fn(char) -> bool| s.chars().skip(1).all(alphabet);
+    match s.chars().next() {
+        Some('Q') => s.len() == 46 && s.starts_with("Qm") && s.chars().all(|c| BASE58_ALPHABET.contains(c)),
+        Some('b') => s.len() >= 50 && in_alphabet(&|c| matches!(c, 'a'..='z' | '2'..='7')),
+        Some('k') => s.len() >= 50 && in_alphabet(&|c| c.is_ascii_digit() || c.is_ascii_lowercase()),
+        Some('z') => s.len() >= 46 && in_alphabet(&|c| BASE58_ALPHABET.contains(c)),
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct GatewayHealth {
+    consecutive_failures: u32,
+    cooldown_until: Option<Instant>,
+}
+
+pub struct GatewayPool {
+    gateways: Vec<String>, // base URLs, in order of preference
+    health: Mutex<Vec<GatewayHealth>>,
+}
+
+impl GatewayPool {
+    pub fn new(gateways: Vec<String>) -> Self {
+        let gateways: Vec<String> = gateways.into_iter().map(|g| g.trim_end_matches('/').to_string()).collect();
+        let health = gateways.iter().map(|_| GatewayHealth::default()).collect();
+        GatewayPool { gateways, health: Mutex::new(health) }
+    }
+
+    // Gateways to try, in order: the healthy ones as configured, then those cooling down,
+    // the one that comes back soonest first (better a slow answer than none).
+    fn ranked(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let health = self.health.lock().unwrap();
+        let mut order: Vec<usize> = (0..self.gateways.len()).collect();
+        order.sort_by_key(|&i| health[i].cooldown_until.filter(|&until| until > now));
+        order
+    }
+
+    fn record(&self, gateway: usize, healthy: bool) {
+        let mut health = self.health.lock().unwrap();
+        let state = &mut health[gateway];
+        if healthy {
+            *state = GatewayHealth::default();
+        } else {
+            state.consecutive_failures += 1;
+            let cooldown = BASE_COOLDOWN.saturating_mul(2u32.saturating_pow(state.consecutive_failures - 1)).min(MAX_COOLDOWN);
+            state.cooldown_until = Some(Instant::now() + cooldown);
+        }
+    }
+
+    // Requests the content from each gateway in turn until one answers without a timeout
+    // or server error. Returns that response (whatever its status) and the URL it came from.
+    pub async fn fetch(&self, client: &Client, ipfs: &IpfsPath, timeout: Duration) -> Result<(Response, String), JobError> {
+        let mut last_error = JobError::new(ErrorClass::Network, "No IPFS gateways configured");
+        for gateway in self.ranked() {
+            let url = format!("{}/ipfs/{}{}", self.gateways[gateway], ipfs.cid, ipfs.path);
+            match client.get(&url).timeout(timeout).send().await {
+                Ok(resp) if resp.status().is_server_error() || resp.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    eprintln!("[IPFS] Gateway {} answered {}, trying the next one", self.gateways[gateway], resp.status());
+                    self.record(gateway, false);
+                    last_error = JobError::new(ErrorClass::HttpStatus, format!("Non-200 status {} from IPFS gateway: {}", resp.status(), url));
+                }
+                Ok(resp) => {
+                    self.record(gateway, true);
+                    return Ok((resp, url));
+                }
+                Err(e) => {
+                    eprintln!("[IPFS] Gateway {} failed ({}), trying the next one", self.gateways[gateway], e);
+                    self.record(gateway, false);
+                    last_error = JobError::new(ErrorClass::Network, format!("HTTP error fetching from IPFS gateway: {} ({})", url, e));
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V0: &str = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+    const V1: &str = "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi";
+
+    fn path(cid: &str, path: &str) -> Option<IpfsPath> {
+        Some(IpfsPath { cid: cid.to_string(), path: path.to_string() })
+    }
+
+    #[test]
+    fn parses_ipfs_scheme() {
+        assert_eq!(parse_ipfs_uri(&format!("ipfs://{V0}")), path(V0, ""));
+        assert_eq!(parse_ipfs_uri(&format!("ipfs://{V1}/1.json")), path(V1, "/1.json"));
+        assert_eq!(parse_ipfs_uri(&format!("ipfs://ipfs/{V0}/meta/7")), path(V0, "/meta/7"));
+    }
+
+    #[test]
+    fn parses_gateway_urls() {
+        assert_eq!(parse_ipfs_uri(&format!("https://gateway.pinata.cloud/ipfs/{V0}/42")), path(V0, "/42"));
+        assert_eq!(parse_ipfs_uri(&format!("https://{V1}.ipfs.dweb.link/image.png")), path(V1, "/image.png"));
+        assert_eq!(parse_ipfs_uri("https://example.com/ipfs/not-a-cid"), None);
+        assert_eq!(parse_ipfs_uri("https://api.example.com/token/1"), None);
+    }
+
+    #[test]
+    fn parses_bare_cids() {
+        assert_eq!(parse_ipfs_uri(V1), path(V1, ""));
+        assert_eq!(parse_ipfs_uri(&format!("/ipfs/{V0}/a.json")), path(V0, "/a.json"));
+        assert_eq!(parse_ipfs_uri("ar://abc"), None);
+    }
+}
@@ -1,24 +1,41 @@
+mod data_uri;
+mod ipfs;
+mod mime;
+mod offsets;
+mod ordering;
+mod renditions;
+mod retry;
+mod scheduler;
+mod storage;
+mod token_uri;
+
 use rdkafka::config::ClientConfig;
-use rdkafka::consumer::{StreamConsumer, Consumer};
-use rdkafka::message::Message;
+use rdkafka::consumer::{CommitMode, StreamConsumer, Consumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::{Message, OwnedMessage};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::TopicPartitionList;
 use std::env;
-use common::NftMintJob; // Assuming 'common' is a crate in your workspace
+use common::chain::ChainAdapter;
+use common::evm::{self, EvmAdapter, EvmChainConfig};
+use common::{CollectionJob, NftMintJob, RefreshJob, RetractionJob, TransferJob}; // Assuming 'common' is a crate in your workspace
 use serde_json;
 use tokio_stream::StreamExt;
 use reqwest::Client;
 use reqwest::StatusCode;
 use std::time::Duration;
-// Removed fs and Path imports as we won't be writing to local filesystem for media cache
-// use std::fs;
-// use std::path::Path;
 use sha2::{Sha256, Digest};
 use sqlx::PgPool;
-use db::{NftMetadata, NftMedia}; // Assuming 'db' is a crate in your workspace
-use aws_sdk_s3::{Client as S3Client, primitives::ByteStream};
-use aws_sdk_s3::config::Credentials;
-use aws_config::Region;
-use aws_config::BehaviorVersion;
+use db::{Collection, MediaRendition, MetadataChange, NftMetadata, NftMedia, Transfer}; // Assuming 'db' is a crate in your workspace
 use anyhow; // Added anyhow explicitly, though it might be transitive
+use ipfs::GatewayPool;
+use retry::{ErrorClass, JobError, RetryEnvelope, RetryPolicy, RetryScheduler, SEND_TIMEOUT};
+use storage::MediaStore;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio_stream::wrappers::ReceiverStream;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct NormalizedMetadata {
@@ -30,36 +47,60 @@ pub struct NormalizedMetadata {
     pub raw: serde_json::Value,
 }
 
-fn resolve_uri(uri: &str) -> String {
-    if uri.starts_with("ipfs://") {
-        let hash = uri.trim_start_matches("ipfs://");
-        format!("https://ipfs.io/ipfs/{}", hash)
-    } else if uri.starts_with("ar://") {
-        let hash = uri.trim_start_matches("ar://");
-        format!("https://arweave.net/{}", hash)
+// Fetches a token or media URI: IPFS content through the gateway pool, Arweave through
+// arweave.net, anything else as is. Returns the response and the URL that served it.
+async fn fetch_uri(client: &Client, gateways: &GatewayPool, uri: &str, timeout: Duration, what: &str) -> Result<(reqwest::Response, String), JobError> {
+    let (resp, resolved) = if let Some(ipfs) = ipfs::parse_ipfs_uri(uri) {
+        gateways.fetch(client, &ipfs, timeout).await?
     } else {
-        uri.to_string()
-    }
-}
-
-async fn fetch_and_normalize_metadata(client: &Client, uri: &str) -> anyhow::Result<NormalizedMetadata> {
-    let resolved = resolve_uri(uri);
-    let resp = client.get(&resolved)
-        .timeout(Duration::from_secs(10))
-        .send().await;
-    let resp = match resp {
-        Ok(r) => r,
-        Err(e) => {
-            return Err(anyhow::anyhow!("HTTP error fetching metadata: {} ({})", resolved, e));
+        let resolved = match uri.strip_prefix("ar://") {
+            Some(hash) => format!("https://arweave.net/{}", hash),
+            None => uri.to_string(),
+        };
+        match client.get(&resolved).timeout(timeout).send().await {
+            Ok(r) => (r, resolved),
+            Err(e) => {
+                return Err(JobError::new(ErrorClass::Network, format!("HTTP error fetching {}: {} ({})", what, resolved, e)));
+            }
         }
     };
     if resp.status() != StatusCode::OK {
-        return Err(anyhow::anyhow!("Non-200 status {} fetching metadata: {}", resp.status(), resolved));
+        return Err(JobError::new(ErrorClass::HttpStatus, format!("Non-200 status {} fetching {}: {}", resp.status(), what, resolved)));
+    }
+    Ok((resp, resolved))
+}
+
+// The body behind a URI. `data:` URIs are decoded in place. `mime_type` is the data URI
+// type or the Content-Type header, when there is one.
+struct Content {
+    bytes: Vec<u8>,
+    resolved: String,
+    mime_type: Option<String>,
+}
+
+async fn fetch_content(client: &Client, gateways: &GatewayPool, uri: &str, timeout: Duration, what: &str) -> Result<Content, JobError> {
+    if let Some(decoded) = data_uri::decode(uri) {
+        let decoded = decoded.map_err(|e| JobError::new(ErrorClass::InvalidMetadata, format!("Invalid {} data URI: {}", what, e)))?;
+        return Ok(Content { bytes: decoded.bytes, resolved: format!("data:{}", decoded.mime_type), mime_type: Some(decoded.mime_type) });
     }
-    let raw: serde_json::Value = match resp.json().await {
+    let (resp, resolved) = fetch_uri(client, gateways, uri, timeout, what).await?;
+    let mime_type = resp.headers().get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|mime_type| mime_type.trim().to_ascii_lowercase())
+        .filter(|mime_type| !mime_type.is_empty());
+    match resp.bytes().await {
+        Ok(bytes) => Ok(Content { bytes: bytes.to_vec(), resolved, mime_type }),
+        Err(e) => Err(JobError::new(ErrorClass::Network, format!("Failed to read {} bytes: {} ({})", what, resolved, e))),
+    }
+}
+
+async fn fetch_and_normalize_metadata(client: &Client, gateways: &GatewayPool, uri: &str) -> Result<NormalizedMetadata, JobError> {
+    let Content { bytes, resolved, .. } = fetch_content(client, gateways, uri, Duration::from_secs(10), "metadata").await?;
+    let raw: serde_json::Value = match serde_json::from_slice(&bytes) {
         Ok(json) => json,
         Err(e) => {
-            return Err(anyhow::anyhow!("Invalid JSON in metadata: {} ({})", resolved, e));
+            return Err(JobError::new(ErrorClass::InvalidMetadata, format!("Invalid JSON in metadata: {} ({})", resolved, e)));
         }
     };
     let name = raw.get("name").and_then(|v| v.as_str()).map(|s| s.to_string());
@@ -70,68 +111,480 @@ async fn fetch_and_normalize_metadata(client: &Client, uri: &str) -> anyhow::Res
     Ok(NormalizedMetadata { name, description, image, animation_url, attributes, raw })
 }
 
-async fn upload_to_s3(s3: &S3Client, bucket: &str, key: &str, bytes: &[u8]) -> anyhow::Result<String> {
-    let body = ByteStream::from(bytes.to_vec());
-    s3.put_object()
-        .bucket(bucket)
-        .key(key)
-        .body(body)
-        .send()
-        .await?;
-    let url = format!("https://{}.s3.amazonaws.com/{}", bucket, key);
-    Ok(url)
+struct CachedMedia {
+    cached_url: String,
+    backend: String,
+    content_hash: String,
+    byte_size: i64,
+    mime_type: String,
+    bytes: Vec<u8>,
+}
+
+// Downloads a media file into the media store. Files are stored under the hash of their
+// content, so a file that is already stored (from any URL) is not uploaded again.
+async fn fetch_and_cache_media(client: &Client, gateways: &GatewayPool, store: &dyn MediaStore, url: &str) -> Result<CachedMedia, JobError> {
+    let Content { bytes, resolved, mime_type: declared_type } = fetch_content(client, gateways, url, Duration::from_secs(20), "media").await?;
+
+    let content_hash = format!("{:x}", Sha256::digest(&bytes));
+
+    let mime_type = mime::detect(&bytes, declared_type.as_deref());
+    let key = format!("{}.{}", content_hash, mime::extension(&mime_type));
+    let stored = |e: anyhow::Error| JobError::new(ErrorClass::Storage, format!("Failed to store media in {}: {}", store.backend(), e));
+    if store.exists(&key).await.map_err(stored)? {
+        println!("Media {} already stored as {}", resolved, key);
+    } else {
+        store.put(&key, &bytes, &mime_type).await.map_err(stored)?;
+    }
+    Ok(CachedMedia {
+        cached_url: store.url(&key),
+        backend: store.backend().to_string(),
+        content_hash,
+        byte_size: bytes.len() as i64,
+        mime_type,
+        bytes,
+    })
 }
 
-// Modified to only upload to S3. If S3 config is missing or upload fails, it returns an error.
-async fn fetch_and_cache_media(client: &Client, s3: Option<&S3Client>, bucket: Option<&str>, url: &str) -> anyhow::Result<(String, String, String)> {
-    let resolved = resolve_uri(url);
-    let resp = client.get(&resolved)
-        .timeout(Duration::from_secs(20))
-        .send().await;
-    let resp = match resp {
-        Ok(r) => r,
+// Stores WebP renditions of a cached image at the configured widths, skipping widths it
+// already has. A width the original is no wider than is recorded with the original's URL, so
+// it is not rendered again; a width whose copy would outweigh the original is skipped. An
+// image that cannot be decoded just gets no renditions.
+async fn create_renditions(store: &dyn MediaStore, pool: &PgPool, widths: &[u32], image: &CachedMedia) -> Result<(), JobError> {
+    if widths.is_empty() || !renditions::is_renderable(&image.mime_type) {
+        return Ok(());
+    }
+    let done = db::media_rendition_widths(pool, &image.content_hash, store.backend()).await?;
+    let missing: Vec<u32> = widths.iter().copied().filter(|&width| !done.contains(&(width as i32))).collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let bytes = image.bytes.clone();
+    let requested = missing.clone();
+    let rendered = tokio::task::spawn_blocking(move || renditions::render(&bytes, &requested))
+        .await
+        .map_err(|e| JobError::new(ErrorClass::Panic, format!("rendering {}: {}", image.content_hash, e)))?;
+    let rendered = match rendered {
+        Ok(rendered) => rendered,
         Err(e) => {
-            return Err(anyhow::anyhow!("HTTP error fetching media: {} ({})", resolved, e));
+            eprintln!("[ERROR] Could not render image {}: {}", image.content_hash, e);
+            return Ok(());
         }
     };
-    if resp.status() != StatusCode::OK {
-        return Err(anyhow::anyhow!("Non-200 status {} fetching media: {}", resp.status(), resolved));
+    for width in missing.iter().filter(|&&width| !rendered.iter().any(|r| r.width == width)) {
+        println!("Skipped {}px rendition of {}: not smaller than the original", width, image.content_hash);
     }
-    let bytes = match resp.bytes().await {
-        Ok(b) => b,
-        Err(e) => {
-            return Err(anyhow::anyhow!("Failed to read media bytes: {} ({})", resolved, e));
+    for rendition in rendered {
+        let (mime_type, cached_url, byte_size) = match &rendition.bytes {
+            Some(bytes) => {
+                let key = format!("{}_{}.webp", image.content_hash, rendition.width);
+                if let Err(e) = store.put(&key, bytes, renditions::MIME_TYPE).await {
+                    return Err(JobError::new(ErrorClass::Storage, format!("Failed to store rendition in {}: {}", store.backend(), e)));
+                }
+                (renditions::MIME_TYPE.to_string(), store.url(&key), bytes.len() as i64)
+            }
+            None => (image.mime_type.clone(), image.cached_url.clone(), image.byte_size),
+        };
+        let row = MediaRendition {
+            content_hash: image.content_hash.clone(),
+            width: rendition.width as i32,
+            height: rendition.height as i32,
+            mime_type,
+            cached_url,
+            storage_backend: store.backend().to_string(),
+            byte_size,
+        };
+        db::insert_media_rendition(pool, &row).await?;
+    }
+    Ok(())
+}
+
+// Stores a collection row, enriched with the contractURI JSON when the contract has one.
+async fn handle_collection_job(worker: &Worker, job: CollectionJob) -> Result<(), JobError> {
+    let contract_metadata = match &job.contract_uri {
+        Some(uri) => match worker.fetch_metadata(uri).await {
+            Ok(normalized) => Some(normalized),
+            Err(e) => {
+                eprintln!("[ERROR] Failed to fetch contract metadata '{}': {}", uri, e);
+                None
+            }
+        },
+        None => None,
+    };
+    let collection = Collection {
+        chain: job.chain,
+        contract_address: job.contract_address,
+        // Fall back to the contractURI name for contracts without an on-chain name()
+        name: job.name.or_else(|| contract_metadata.as_ref().and_then(|m| m.name.clone())),
+        symbol: job.symbol,
+        token_standard: job.token_standard,
+        total_supply: job.total_supply,
+        first_seen_block: job.first_seen_block.map(|block| block as i64),
+        contract_uri: job.contract_uri,
+        contract_metadata: contract_metadata.map(|m| m.raw),
+    };
+    db::upsert_collection(&worker.pool, &collection).await?;
+    Ok(())
+}
+
+// Hides tokens minted in a block the listener saw reorganized away and undoes its transfers.
+async fn handle_retraction_job(pool: &PgPool, job: RetractionJob) -> Result<(), JobError> {
+    let count = db::orphan_block(pool, &job.chain, job.block_number as i64, &job.block_hash).await?;
+    println!("Orphaned {} tokens minted in {} block {} ({})", count, job.chain, job.block_number, job.block_hash);
+    let reverted = db::revert_transfers(pool, &job.chain, &job.block_hash).await?;
+    println!("Reverted {} transfers from {} block {}", reverted, job.chain, job.block_number);
+    Ok(())
+}
+
+async fn handle_transfer_job(pool: &PgPool, job: TransferJob) -> Result<(), JobError> {
+    if job.token_ids.len() != job.amounts.len() {
+        return Err(JobError::new(ErrorClass::InvalidPayload, "token_ids and amounts differ in length"));
+    }
+    let transfer = Transfer {
+        chain: job.chain,
+        contract_address: job.contract_address,
+        token_standard: job.token_standard,
+        from_address: job.from_address,
+        to_address: job.to_address,
+        token_ids: job.token_ids,
+        amounts: job.amounts,
+        tx_hash: job.tx_hash,
+        block_number: job.block_number as i64,
+        block_hash: job.block_hash,
+        log_index: job.log_index as i64,
+    };
+    db::record_transfer(pool, &transfer).await?;
+    Ok(())
+}
+
+// Fetches the metadata behind a token URI, expanding ERC-1155 `{id}` templates. When the
+// template has more than one reading, a non-200 answer moves on to the next.
+async fn fetch_token_metadata(worker: &Worker, uri: &str, token_id: &str) -> Result<NormalizedMetadata, JobError> {
+    let mut candidates = token_uri::candidates(uri, token_id).into_iter().peekable();
+    while let Some(candidate) = candidates.next() {
+        match worker.fetch_metadata(&candidate).await {
+            Err(e) if e.class == ErrorClass::HttpStatus && candidates.peek().is_some() => {
+                println!("No metadata at {} ({}), trying the next id format", candidate, e);
+            }
+            result => return result,
+        }
+    }
+    unreachable!("token_uri::candidates returns at least one URI")
+}
+
+async fn handle_mint_job(worker: &Worker, job: NftMintJob) -> Result<(), JobError> {
+    let Some(token_uri) = &job.metadata_uri else {
+        return Err(JobError::new(ErrorClass::MissingUri, "No metadata_uri in job"));
+    };
+    let block_number = job.block_number.map(|block| block as i64);
+    index_token(worker, &job.chain, &job.contract_address, &job.token_id, token_uri, block_number, job.block_hash).await?;
+    Ok(())
+}
+
+// Fetches a token's metadata again from the URI in the job, or else the one the contract
+// returns now or the one stored when it was indexed. Media are re-checked too, since an
+// image can change behind the same URL.
+async fn handle_refresh_job(worker: &Worker, job: RefreshJob) -> Result<(), JobError> {
+    if let Some(to_token_id) = &job.to_token_id {
+        return handle_refresh_range(worker, &job, to_token_id).await;
+    }
+    let token_uri = match job.metadata_uri.clone() {
+        // An ERC-1155 URI event names the token's URI, which is all a mint needs: a token
+        // whose mint failed for want of one (its `uri(id)` reverts) is indexed from it.
+        Some(uri) => uri,
+        None => {
+            // Metadata updates name ranges of ids, some of which may never have been minted;
+            // only indexed tokens are refreshed, so no rows appear for tokens that do not exist.
+            let Some(stored_uri) = db::find_token_uri(&worker.pool, &job.chain, &job.contract_address, &job.token_id).await? else {
+                println!("Skipped refresh of {} {}/{}: token not indexed", job.chain, job.contract_address, job.token_id);
+                return Ok(());
+            };
+            match worker.current_token_uri(&job).await? {
+                Some(uri) => uri,
+                None => stored_uri.ok_or_else(|| JobError::new(ErrorClass::MissingUri, "No stored token URI to refresh from"))?,
+            }
         }
     };
+    let change = index_token(worker, &job.chain, &job.contract_address, &job.token_id, &token_uri, None, None).await?;
+    println!("Refreshed {} {}/{}: metadata {:?}", job.chain, job.contract_address, job.token_id, change);
+    Ok(())
+}
 
-    // Hash the original URL for a unique filename
-    let mut hasher = Sha256::new();
-    hasher.update(url.as_bytes());
-    let hash = format!("{:x}", hasher.finalize());
+// A metadata update too wide for the listener to expand: queues a refresh of every token
+// in the range that has been indexed, each from its stored token URI. A failed send
+// fails the job, and the retry queues the range again.
+async fn handle_refresh_range(worker: &Worker, job: &RefreshJob, to_token_id: &str) -> Result<(), JobError> {
+    let token_ids = db::token_ids_in_range(&worker.pool, &job.chain, &job.contract_address, &job.token_id, to_token_id).await?;
+    println!("Refreshing {} indexed tokens of {} {} in {}..={}", token_ids.len(), job.chain, job.contract_address, job.token_id, to_token_id);
+    for token_id in token_ids {
+        let refresh = RefreshJob {
+            chain: job.chain.clone(),
+            contract_address: job.contract_address.clone(),
+            token_id,
+            metadata_uri: None,
+            to_token_id: None,
+        };
+        let payload = serde_json::to_string(&refresh).expect("refresh job serializes");
+        let record = FutureRecord::to(&worker.refresh_topic).payload(&payload).key(&refresh.contract_address);
+        if let Err((e, _)) = worker.producer.send(record, SEND_TIMEOUT).await {
+            return Err(JobError::new(ErrorClass::Network, format!("Failed to queue refresh of token {}: {}", refresh.token_id, e)));
+        }
+    }
+    Ok(())
+}
 
-    // Determine extension from original URL (or default to "bin")
-    let ext_pos = resolved.rfind('.').map_or(resolved.len(), |idx| idx + 1);
-    let ext = &resolved[ext_pos..];
-    let ext = if ext.is_empty() || ext.contains('/') || ext.contains('\\') {
-        "bin" // Fallback if no valid extension found or it's part of a path
-    } else {
-        ext
+// Fetches a token's metadata, stores it, then caches its image and animation. Any failure
+// fails the whole job; a retry starts over, which the idempotent inserts allow.
+async fn index_token(
+    worker: &Worker,
+    chain: &str,
+    contract_address: &str,
+    token_id: &str,
+    token_uri: &str,
+    block_number: Option<i64>,
+    block_hash: Option<String>,
+) -> Result<MetadataChange, JobError> {
+    let normalized = fetch_token_metadata(worker, token_uri, token_id).await?;
+    println!("Normalized metadata: {:?}", normalized);
+    // Store metadata in DB
+    let meta = NftMetadata {
+        contract_address: contract_address.to_string(),
+        token_id: token_id.to_string(),
+        chain: chain.to_string(),
+        name: normalized.name.clone(),
+        description: normalized.description.clone(),
+        attributes: normalized.attributes.clone(),
+        raw_metadata: normalized.raw.clone(),
+        block_number,
+        block_hash,
+        token_uri: Some(token_uri.to_string()),
     };
+    let change = db::insert_nft_metadata(&worker.pool, &meta).await?;
+    if change == MetadataChange::Retracted {
+        // Minted in a block that was reorganized away; nothing to serve, so skip the media.
+        println!("Skipped media for {} {}/{}: block retracted", chain, contract_address, token_id);
+        return Ok(change);
+    }
 
+    // Fetch and cache media (image, animation_url)
+    for (media_type, url) in [("image", &normalized.image), ("animation", &normalized.animation_url)] {
+        let Some(url) = url else {
+            continue;
+        };
+        let cached = worker.cache_media(url).await?;
+        println!("Cached {} to: {} (backend: {})", media_type, cached.cached_url, cached.backend);
+        if media_type == "image" {
+            worker.render_image(&cached).await?;
+        }
+        let media = NftMedia {
+            chain: chain.to_string(),
+            contract_address: contract_address.to_string(),
+            token_id: token_id.to_string(),
+            media_type: media_type.to_string(),
+            original_url: url.to_string(),
+            cached_url: cached.cached_url,
+            storage_backend: cached.backend,
+            content_hash: cached.content_hash,
+            byte_size: cached.byte_size,
+            mime_type: Some(cached.mime_type),
+        };
+        db::insert_nft_media(&worker.pool, &media).await?;
+    }
+    Ok(change)
+}
 
-    // If S3 is configured, upload and return S3 URL
-    if let (Some(s3), Some(bucket)) = (s3, bucket) {
-        let s3_key = format!("{}.{}", hash, ext);
-        match upload_to_s3(s3, bucket, &s3_key, &bytes).await {
-            Ok(s3_url) => return Ok((s3_url, resolved, "s3".to_string())),
+struct Worker {
+    client: Client,
+    pool: PgPool,
+    media_store: Box<dyn MediaStore>,
+    // Queues the per-token jobs of a range refresh
+    producer: FutureProducer,
+    kafka_topic: String,
+    collection_topic: String,
+    retraction_topic: String,
+    refresh_topic: String,
+    transfer_topic: String,
+    gateways: GatewayPool,
+    // Separate download limits, so slow media never starves metadata fetches.
+    metadata_fetches: Semaphore,
+    media_fetches: Semaphore,
+    rendition_widths: Vec<u32>,
+    // RPC connections by chain name, for reading token URIs on refresh
+    chains: HashMap<String, Box<dyn ChainAdapter>>,
+}
+
+impl Worker {
+    // The token URI the contract returns now, so a refresh picks up a changed base URI.
+    // None when the chain has no RPC configured, the token is unknown or the call fails;
+    // the stored token URI is used then.
+    async fn current_token_uri(&self, job: &RefreshJob) -> Result<Option<String>, JobError> {
+        let Some(adapter) = self.chains.get(&job.chain) else {
+            return Ok(None);
+        };
+        let Some(token_standard) = db::token_standard(&self.pool, &job.chain, &job.contract_address, &job.token_id).await? else {
+            return Ok(None);
+        };
+        match adapter.token_uri(&job.contract_address, &job.token_id, &token_standard).await {
+            Ok(uri) if !uri.is_empty() => Ok(Some(uri)),
+            Ok(_) => Ok(None),
             Err(e) => {
-                // If S3 upload fails, we now return an error instead of falling back to local.
-                return Err(anyhow::anyhow!("Failed to upload to S3: {}", e));
+                eprintln!("[WARN] Could not read the token URI of {} {}/{} on-chain: {}", job.chain, job.contract_address, job.token_id, e);
+                Ok(None)
             }
         }
-    } else {
-        // If S3 is NOT configured, we also return an error because local caching is removed.
-        return Err(anyhow::anyhow!("S3 not configured for media caching. No local fallback enabled."));
+    }
+
+    async fn fetch_metadata(&self, uri: &str) -> Result<NormalizedMetadata, JobError> {
+        let _slot = self.metadata_fetches.acquire().await.expect("fetch semaphore is never closed");
+        fetch_and_normalize_metadata(&self.client, &self.gateways, uri).await
+    }
+
+    async fn cache_media(&self, url: &str) -> Result<CachedMedia, JobError> {
+        let _slot = self.media_fetches.acquire().await.expect("fetch semaphore is never closed");
+        fetch_and_cache_media(&self.client, &self.gateways, self.media_store.as_ref(), url).await
+    }
+
+    // Renditions are CPU work on the media file, so they share the media limit.
+    async fn render_image(&self, image: &CachedMedia) -> Result<(), JobError> {
+        let _slot = self.media_fetches.acquire().await.expect("fetch semaphore is never closed");
+        create_renditions(self.media_store.as_ref(), &self.pool, &self.rendition_widths, image).await
+    }
+
+    // Runs one job, picking its type from the topic it was originally published to.
+    async fn dispatch(&self, topic: &str, payload: &[u8]) -> Result<(), JobError> {
+        let invalid = |e: serde_json::Error| JobError::new(ErrorClass::InvalidPayload, format!("Failed to deserialize job: {e}"));
+        if topic == self.collection_topic {
+            let job: CollectionJob = serde_json::from_slice(payload).map_err(invalid)?;
+            println!("Received collection job: {:?}", job);
+            handle_collection_job(self, job).await
+        } else if topic == self.retraction_topic {
+            let job: RetractionJob = serde_json::from_slice(payload).map_err(invalid)?;
+            println!("Received retraction job: {:?}", job);
+            handle_retraction_job(&self.pool, job).await
+        } else if topic == self.refresh_topic {
+            let job: RefreshJob = serde_json::from_slice(payload).map_err(invalid)?;
+            println!("Received refresh job: {:?}", job);
+            handle_refresh_job(self, job).await
+        } else if topic == self.transfer_topic {
+            let job: TransferJob = serde_json::from_slice(payload).map_err(invalid)?;
+            handle_transfer_job(&self.pool, job).await
+        } else if topic == self.kafka_topic {
+            let job: NftMintJob = serde_json::from_slice(payload).map_err(invalid)?;
+            println!("Received job: {:?}", job);
+            handle_mint_job(self, job).await
+        } else {
+            Err(JobError::new(ErrorClass::InvalidPayload, format!("No handler for topic {topic}")))
+        }
+    }
+}
+
+// Runs the messages of `consumer` through `scheduler::run`, storing and committing
+// offsets as jobs finish. Replayed jobs are safe because every insert the worker makes is
+// an upsert.
+async fn consume<F, Fut>(consumer: StreamConsumer, max_in_flight: usize, ordered_topics: &[String], handle: F)
+where
+    F: Fn(OwnedMessage, OwnedSemaphorePermit) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let messages = consumer.stream().map(|message| message.map(|m| m.detach()));
+    schedule(&consumer, messages, max_in_flight, ordered_topics, handle).await;
+}
+
+// Like `consume`, for retry envelopes: each reaches the scheduler once its attempt is due,
+// with its partition paused until then, so waiting retries hold neither a slot nor a task
+// and the partition's offset is committed in order.
+async fn consume_retries<F, Fut>(consumer: StreamConsumer, max_in_flight: usize, handle: F)
+where
+    F: Fn(OwnedMessage, OwnedSemaphorePermit) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let messages = consumer.stream().map(|message| message.map(|m| m.detach()));
+    let pause = |topic: &str, partition: i32, pause: bool| {
+        let mut partitions = TopicPartitionList::new();
+        partitions.add_partition(topic, partition);
+        let result = if pause { consumer.pause(&partitions) } else { consumer.resume(&partitions) };
+        if let Err(e) = result {
+            eprintln!("[ERROR] Failed to {} {}/{}: {}", if pause { "pause" } else { "resume" }, topic, partition, e);
+        }
+    };
+    let (due, due_messages) = mpsc::channel(1);
+    tokio::join!(
+        scheduler::hold_until_due(messages, retry_wait, pause, due),
+        schedule(&consumer, ReceiverStream::new(due_messages), max_in_flight, &[], handle),
+    );
+}
+
+async fn schedule<S, F, Fut>(consumer: &StreamConsumer, messages: S, max_in_flight: usize, ordered_topics: &[String], handle: F)
+where
+    S: tokio_stream::Stream<Item = Result<OwnedMessage, KafkaError>> + Unpin,
+    F: Fn(OwnedMessage, OwnedSemaphorePermit) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    scheduler::run(messages, max_in_flight, ordered_topics, handle, |topic, partition, done_upto| {
+        if let Err(e) = consumer.store_offset(topic, partition, done_upto) {
+            eprintln!("[ERROR] Failed to store offset {} for {}/{}: {}", done_upto, topic, partition, e);
+        } else if let Err(e) = consumer.commit_consumer_state(CommitMode::Async) {
+            eprintln!("[ERROR] Failed to commit offset {} for {}/{}: {}", done_upto, topic, partition, e);
+        }
+    })
+    .await;
+}
+
+// How long until a retry envelope's attempt is due; envelopes that cannot be decoded are
+// due now, and `process_retry` reports them.
+fn retry_wait(m: &OwnedMessage) -> Duration {
+    m.payload()
+        .and_then(|payload| serde_json::from_slice::<RetryEnvelope>(payload).ok())
+        .map(|envelope| envelope.wait())
+        .unwrap_or_default()
+}
+
+// A new job; failures enter the retry pipeline as attempt 1.
+async fn process_job(worker: Arc<Worker>, retries: Arc<RetryScheduler>, m: OwnedMessage, _slot: OwnedSemaphorePermit) {
+    let Some(payload) = m.payload() else {
+        return;
+    };
+    if let Err(e) = run_job(worker, m.topic().to_string(), payload.to_vec()).await {
+        let key = m.key().map(|k| String::from_utf8_lossy(k).into_owned());
+        retries.job_failed(m.topic(), key.as_deref(), payload_json(payload), 1, e).await;
+    }
+}
+
+// A retry envelope, handed over once its attempt is due: runs the original job again.
+async fn process_retry(worker: Arc<Worker>, retries: Arc<RetryScheduler>, m: OwnedMessage, _slot: OwnedSemaphorePermit) {
+    let envelope: RetryEnvelope = match m.payload().map(serde_json::from_slice) {
+        Some(Ok(envelope)) => envelope,
+        Some(Err(e)) => {
+            eprintln!("[ERROR] Failed to deserialize retry envelope: {e}");
+            return;
+        }
+        None => return,
+    };
+    let attempt = envelope.attempt + 1;
+    println!("Retrying job from {} (attempt {})", envelope.original_topic, attempt);
+    let payload = serde_json::to_vec(&envelope.payload).expect("JSON value serializes");
+    if let Err(e) = run_job(worker, envelope.original_topic.clone(), payload).await {
+        retries.job_failed(&envelope.original_topic, envelope.key.as_deref(), envelope.payload, attempt, e).await;
+    }
+}
+
+// Runs a job on its own task, so a panic while handling it fails only that job, which
+// then goes to the dead-letter topic like any other failure that cannot be retried.
+async fn run_job(worker: Arc<Worker>, topic: String, payload: Vec<u8>) -> Result<(), JobError> {
+    tokio::spawn(async move { worker.dispatch(&topic, &payload).await })
+        .await
+        .unwrap_or_else(|e| Err(JobError::new(ErrorClass::Panic, e.to_string())))
+}
+
+// The job as JSON for the retry and dead-letter records; undecodable payloads are kept as a string.
+fn payload_json(payload: &[u8]) -> serde_json::Value {
+    serde_json::from_slice(payload).unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(payload).into_owned()))
+}
+
+// Parses an optional numeric env var, falling back to `default` when unset.
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| panic!("{name} must be a valid number")),
+        Err(_) => default,
     }
 }
 
@@ -140,6 +593,53 @@ async fn main() -> anyhow::Result<()> {
     // Load Kafka and DB config from env
     let kafka_brokers = env::var("KAFKA_BROKERS").expect("KAFKA_BROKERS must be set");
     let kafka_topic = env::var("KAFKA_TOPIC").unwrap_or_else(|_| "nft_mint_jobs".to_string());
+    let collection_topic = env::var("KAFKA_COLLECTION_TOPIC").unwrap_or_else(|_| "nft_collection_jobs".to_string());
+    let retraction_topic = env::var("KAFKA_RETRACTION_TOPIC").unwrap_or_else(|_| "nft_retraction_jobs".to_string());
+    let refresh_topic = env::var("KAFKA_REFRESH_TOPIC").unwrap_or_else(|_| "nft_refresh_jobs".to_string());
+    let transfer_topic = env::var("KAFKA_TRANSFER_TOPIC").unwrap_or_else(|_| "nft_transfer_jobs".to_string());
+    let retry_topic = env::var("KAFKA_RETRY_TOPIC").unwrap_or_else(|_| "nft_jobs_retry".to_string());
+    let dead_letter_topic = env::var("KAFKA_DEAD_LETTER_TOPIC").unwrap_or_else(|_| "nft_jobs_dead_letter".to_string());
+    let retry_policy = RetryPolicy {
+        max_attempts: env_or("JOB_MAX_ATTEMPTS", 5),
+        initial_backoff: Duration::from_secs(env_or("JOB_RETRY_BACKOFF_SECS", 30)),
+        max_backoff: Duration::from_secs(env_or("JOB_RETRY_MAX_BACKOFF_SECS", 3600)),
+    };
+    let max_in_flight_jobs: usize = env_or("WORKER_CONCURRENCY", 32);
+    let max_metadata_fetches: usize = env_or("METADATA_FETCH_CONCURRENCY", 16);
+    let max_media_fetches: usize = env_or("MEDIA_FETCH_CONCURRENCY", 8);
+    // Widths of the WebP renditions made of every image; empty to disable them.
+    let rendition_widths: Vec<u32> = env::var("RENDITION_WIDTHS")
+        .unwrap_or_else(|_| "256,512,1024".to_string())
+        .split(',')
+        .filter_map(|width| width.trim().parse().ok())
+        .collect();
+    // Gateways are tried in order; a local Kubo node (IPFS_LOCAL_GATEWAY, e.g.
+    // http://127.0.0.1:8080) goes first, since it is the fastest and has no rate limits.
+    let mut ipfs_gateways: Vec<String> = env::var("IPFS_LOCAL_GATEWAY").ok().into_iter().collect();
+    ipfs_gateways.extend(
+        env::var("IPFS_GATEWAYS")
+            .unwrap_or_else(|_| "https://ipfs.io,https://dweb.link,https://gateway.pinata.cloud".to_string())
+            .split(',')
+            .map(|gateway| gateway.trim().to_string())
+            .filter(|gateway| !gateway.is_empty()),
+    );
+    // Refreshes read token URIs on-chain for every chain in CHAINS (default ethereum) with
+    // a <CHAIN>_HTTP_URL set; <CHAIN>_CHAIN_ID is needed for chains other than ethereum,
+    // polygon, base and arbitrum. Other chains refresh from the stored token URI.
+    let mut chain_configs = Vec::new();
+    for name in env::var("CHAINS").unwrap_or_else(|_| "ethereum".to_string()).split(',') {
+        let name = name.trim().to_lowercase();
+        let prefix = name.to_uppercase();
+        let Ok(rpc_url) = env::var(format!("{prefix}_HTTP_URL")) else {
+            eprintln!("[WARN] {prefix}_HTTP_URL is not set; {name} tokens are refreshed from their stored token URI");
+            continue;
+        };
+        let chain_id = match env::var(format!("{prefix}_CHAIN_ID")) {
+            Ok(id) => id.parse().unwrap_or_else(|_| panic!("{prefix}_CHAIN_ID must be a valid number")),
+            Err(_) => evm::known_chain_id(&name).unwrap_or_else(|| panic!("{prefix}_CHAIN_ID must be set for chain '{name}'")),
+        };
+        chain_configs.push(EvmChainConfig { name, chain_id, rpc_url });
+    }
     let group_id = env::var("KAFKA_GROUP_ID").unwrap_or_else(|_| "metadata_worker_group".to_string());
     
     // --- START: ADDED/UPDATED KAFKA SASL/SSL CONFIGURATION ---
@@ -153,29 +653,18 @@ async fn main() -> anyhow::Result<()> {
     let db_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let pool = PgPool::connect(&db_url).await?;
 
-    // S3 config
-    let s3_bucket = env::var("S3_BUCKET").ok();
-    let s3_region = env::var("AWS_REGION").ok();
-    let s3_access_key = env::var("AWS_ACCESS_KEY_ID").ok();
-    let s3_secret_key = env::var("AWS_SECRET_ACCESS_KEY").ok();
-    let s3_client = if let (Some(region), Some(access_key), Some(secret_key)) = (s3_region.clone(), s3_access_key.clone(), s3_secret_key.clone()) {
-        let region_provider = Region::new(region);
-        let credentials_provider = Credentials::new(access_key, secret_key, None, None, "env");
-        let sdk_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
-            .region(region_provider)
-            .credentials_provider(credentials_provider)
-            .load()
-            .await;
-        Some(S3Client::new(&sdk_config))
-    } else {
-        None
-    };
+    let mut chains: HashMap<String, Box<dyn ChainAdapter>> = HashMap::new();
+    for config in chain_configs {
+        let name = config.name.clone();
+        chains.insert(name, Box::new(EvmAdapter::connect_http(config).await?));
+    }
+
+    let media_store = storage::from_env().await?;
+    println!("Caching media in the {} store", media_store.backend());
 
-    // Set up Kafka consumer
-    let consumer: StreamConsumer = ClientConfig::new()
+    // Set up Kafka clients
+    let kafka_config = ClientConfig::new()
         .set("bootstrap.servers", &kafka_brokers)
-        .set("group.id", &group_id)
-        .set("auto.offset.reset", "earliest")
         // --- START: APPLYING KAFKA SASL/SSL SETTINGS TO CLIENT CONFIG ---
         .set("security.protocol", &security_protocol)
         .set("sasl.mechanisms", &sasl_mechanisms)
@@ -183,90 +672,130 @@ async fn main() -> anyhow::Result<()> {
         .set("sasl.password", &sasl_password)
         .set("session.timeout.ms", &session_timeout_ms)
         // --- END: APPLYING KAFKA SASL/SSL SETTINGS TO CLIENT CONFIG ---
+        .clone();
+    // Offsets are committed by `consume` once jobs finish, never on receipt.
+    let mut consumer_config = kafka_config.clone();
+    consumer_config
+        .set("auto.offset.reset", "earliest")
+        .set("enable.auto.commit", "false")
+        .set("enable.auto.offset.store", "false");
+
+    let consumer: StreamConsumer = consumer_config.clone()
+        .set("group.id", &group_id)
         .create()
         .expect("Failed to create Kafka consumer");
+    // Retries get their own consumer group, so waiting out a backoff never blocks new jobs.
+    let retry_consumer: StreamConsumer = consumer_config
+        .set("group.id", format!("{group_id}_retry"))
+        .create()
+        .expect("Failed to create Kafka retry consumer");
+    let producer: FutureProducer = kafka_config.create().expect("Failed to create Kafka producer");
 
-    consumer.subscribe(&[&kafka_topic])?;
-    println!("Metadata worker listening to Kafka topic: {}", kafka_topic);
-
-    let client = Client::new();
-    let mut message_stream = consumer.stream();
-    while let Some(message) = message_stream.next().await {
-        match message {
-            Ok(m) => {
-                if let Some(payload) = m.payload() {
-                    match serde_json::from_slice::<NftMintJob>(payload) {
-                        Ok(job) => {
-                            println!("Received job: {:?}", job);
-                            if let Some(token_uri) = &job.metadata_uri {
-                                match fetch_and_normalize_metadata(&client, token_uri).await {
-                                    Ok(normalized) => {
-                                        println!("Normalized metadata: {:?}", normalized);
-                                        // Store metadata in DB
-                                        let meta = NftMetadata {
-                                            contract_address: job.contract_address.clone(),
-                                            token_id: job.token_id.clone(),
-                                            chain: job.chain.clone(),
-                                            name: normalized.name.clone(),
-                                            description: normalized.description.clone(),
-                                            attributes: normalized.attributes.clone(),
-                                            raw_metadata: normalized.raw.clone(),
-                                        };
-                                        if let Err(e) = db::insert_nft_metadata(&pool, &meta).await {
-                                            eprintln!("[ERROR] Failed to insert metadata into DB: {}", e);
-                                        }
-                                        // Fetch and cache media (image, animation_url)
-                                        if let Some(image_url) = &normalized.image {
-                                            match fetch_and_cache_media(&client, s3_client.as_ref(), s3_bucket.as_deref(), image_url).await {
-                                                Ok((cached_url, resolved_url, backend)) => {
-                                                    println!("Cached image to: {} (backend: {})", cached_url, backend);
-                                                    let media = NftMedia {
-                                                        contract_address: job.contract_address.clone(),
-                                                        token_id: job.token_id.clone(),
-                                                        media_type: "image".to_string(),
-                                                        original_url: image_url.to_string(),
-                                                        cached_url,
-                                                        storage_backend: backend,
-                                                    };
-                                                    if let Err(e) = db::insert_nft_media(&pool, &media).await {
-                                                        eprintln!("[ERROR] Failed to insert image media into DB: {}", e);
-                                                    }
-                                                }
-                                                Err(e) => eprintln!("[ERROR] Failed to cache image: {}", e),
-                                            }
-                                        }
-                                        if let Some(anim_url) = &normalized.animation_url {
-                                            match fetch_and_cache_media(&client, s3_client.as_ref(), s3_bucket.as_deref(), anim_url).await {
-                                                Ok((cached_url, resolved_url, backend)) => {
-                                                    println!("Cached animation to: {} (backend: {})", cached_url, backend);
-                                                    let media = NftMedia {
-                                                        contract_address: job.contract_address.clone(),
-                                                        token_id: job.token_id.clone(),
-                                                        media_type: "animation".to_string(),
-                                                        original_url: anim_url.to_string(),
-                                                        cached_url,
-                                                        storage_backend: backend,
-                                                    };
-                                                    if let Err(e) = db::insert_nft_media(&pool, &media).await {
-                                                        eprintln!("[ERROR] Failed to insert animation media into DB: {}", e);
-                                                    }
-                                                }
-                                                Err(e) => eprintln!("[ERROR] Failed to cache animation: {}", e),
-                                            }
-                                        }
-                                    }
-                                    Err(e) => eprintln!("[ERROR] Failed to fetch/normalize metadata for token_uri '{}': {}", token_uri, e),
-                                }
-                            } else {
-                                eprintln!("[ERROR] No metadata_uri in job");
-                            }
-                        }
-                        Err(e) => eprintln!("[ERROR] Failed to deserialize job: {e}"),
-                    }
-                }
-            }
-            Err(e) => eprintln!("[ERROR] Kafka error: {e}"),
+    consumer.subscribe(&[&kafka_topic, &collection_topic, &retraction_topic, &refresh_topic, &transfer_topic])?;
+    retry_consumer.subscribe(&[&retry_topic])?;
+    println!(
+        "Metadata worker listening to Kafka topics: {}, {}, {}, {}, {} (retries on {})",
+        kafka_topic, collection_topic, retraction_topic, refresh_topic, transfer_topic, retry_topic
+    );
+
+    // Transfers move balances and refreshes overwrite each other, so a contract's jobs of
+    // these kinds run in order; mints and collections do not depend on each other.
+    let ordered_topics = [refresh_topic.clone(), transfer_topic.clone()];
+    let worker = Arc::new(Worker {
+        client: Client::new(),
+        pool: pool.clone(),
+        media_store,
+        producer: producer.clone(),
+        kafka_topic,
+        collection_topic,
+        retraction_topic,
+        refresh_topic,
+        transfer_topic,
+        gateways: GatewayPool::new(ipfs_gateways),
+        metadata_fetches: Semaphore::new(max_metadata_fetches),
+        media_fetches: Semaphore::new(max_media_fetches),
+        rendition_widths,
+        chains,
+    });
+    let retries = Arc::new(RetryScheduler { producer, pool, policy: retry_policy, retry_topic, dead_letter_topic });
+    tokio::join!(
+        consume(consumer, max_in_flight_jobs, &ordered_topics, |m, slot| process_job(worker.clone(), retries.clone(), m, slot)),
+        // A retry already runs out of order, and waiting for one would hold up its whole key.
+        consume_retries(retry_consumer, max_in_flight_jobs, |m, slot| process_retry(worker.clone(), retries.clone(), m, slot)),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Refresh jobs with a data: URI and no image never reach the media store.
+    struct NoStore;
+
+    #[async_trait::async_trait]
+    impl MediaStore for NoStore {
+        fn backend(&self) -> &'static str {
+            "none"
+        }
+        fn url(&self, key: &str) -> String {
+            key.to_string()
+        }
+        async fn exists(&self, _key: &str) -> anyhow::Result<bool> {
+            Ok(false)
+        }
+        async fn put(&self, _key: &str, _bytes: &[u8], _content_type: &str) -> anyhow::Result<()> {
+            anyhow::bail!("media are not stored in this test")
         }
     }
-    Ok(())
+
+    fn worker(pool: PgPool) -> Worker {
+        Worker {
+            client: Client::new(),
+            pool,
+            media_store: Box::new(NoStore),
+            // Never connects: nothing in these tests sends.
+            producer: ClientConfig::new().set("bootstrap.servers", "localhost:9092").create().unwrap(),
+            kafka_topic: "nft_mint_jobs".to_string(),
+            collection_topic: "nft_collection_jobs".to_string(),
+            retraction_topic: "nft_retraction_jobs".to_string(),
+            refresh_topic: "nft_refresh_jobs".to_string(),
+            transfer_topic: "nft_transfer_jobs".to_string(),
+            gateways: GatewayPool::new(Vec::new()),
+            metadata_fetches: Semaphore::new(1),
+            media_fetches: Semaphore::new(1),
+            rendition_widths: Vec::new(),
+            chains: HashMap::new(),
+        }
+    }
+
+    fn refresh(metadata_uri: Option<&str>) -> RefreshJob {
+        RefreshJob {
+            chain: "ethereum".to_string(),
+            contract_address: "0xabc".to_string(),
+            token_id: "7".to_string(),
+            metadata_uri: metadata_uri.map(str::to_string),
+            to_token_id: None,
+        }
+    }
+
+    async fn stored_uri(pool: &PgPool) -> Option<Option<String>> {
+        db::find_token_uri(pool, "ethereum", "0xabc", "7").await.unwrap()
+    }
+
+    // The token's mint was dead-lettered because `uri(id)` reverts; its URI event indexes it.
+    #[sqlx::test(migrations = "./db/migrations")]
+    async fn refresh_with_uri_indexes_unindexed_token(pool: PgPool) {
+        let worker = worker(pool.clone());
+        let uri = r#"data:application/json,{"name":"Token 7"}"#;
+        handle_refresh_job(&worker, refresh(Some(uri))).await.unwrap();
+        assert_eq!(stored_uri(&pool).await, Some(Some(uri.to_string())));
+    }
+
+    #[sqlx::test(migrations = "./db/migrations")]
+    async fn refresh_without_uri_skips_unindexed_token(pool: PgPool) {
+        let worker = worker(pool.clone());
+        handle_refresh_job(&worker, refresh(None)).await.unwrap();
+        assert_eq!(stored_uri(&pool).await, None);
+    }
 }
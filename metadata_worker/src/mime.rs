@@ -0,0 +1,142 @@
+// Media type detection for cached files.
+//
+// Gateways and NFT hosts often send no Content-Type or a generic one, and URLs rarely end
+// in a usable extension, so the type is read from the file's magic bytes first. The
+// declared type (Content-Type header or data URI type) is only used when sniffing finds
+// nothing.
+
+const OCTET_STREAM: &str = "application/octet-stream";
+
+// Types that say nothing about the content.
+const GENERIC_TYPES: &[&str] = &[OCTET_STREAM, "binary/octet-stream", "application/unknown", "text/plain"];
+
+pub fn detect(bytes: &[u8], declared: Option<&str>) -> String {
+    if let Some(sniffed) = sniff(bytes) {
+        return sniffed.to_string();
+    }
+    match declared {
+        Some(declared) if !GENERIC_TYPES.contains(&declared) => declared.to_string(),
+        _ => OCTET_STREAM.to_string(),
+    }
+}
+
+pub fn extension(mime_type: &str) -> &'static str {
+    match mime_type {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/avif" => "avif",
+        "image/bmp" => "bmp",
+        "image/svg+xml" => "svg",
+        "video/mp4" => "mp4",
+        "video/quicktime" => "mov",
+        "video/webm" => "webm",
+        "audio/mpeg" => "mp3",
+        "audio/wav" => "wav",
+        "audio/ogg" => "ogg",
+        "model/gltf-binary" => "glb",
+        "model/gltf+json" => "gltf",
+        "text/html" => "html",
+        "application/json" => "json",
+        _ => "bin",
+    }
+}
+
+// Whether the file must be served as a download (Content-Disposition: attachment). Token
+// media is untrusted: HTML, SVG and other markup run their scripts on the media domain when
+// opened directly. <img>, <video> and <audio> ignore the header, so SVG images still show.
+pub fn is_served_as_attachment(mime_type: &str) -> bool {
+    let media = ["image/", "video/", "audio/", "model/"].iter().any(|prefix| mime_type.starts_with(prefix));
+    !media || mime_type == "image/svg+xml"
+}
+
+fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    let at = |offset: usize, magic: &[u8]| bytes.get(offset..offset + magic.len()) == Some(magic);
+    if at(0, b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if at(0, b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        Some("image/gif")
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        Some("image/webp")
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        Some("audio/wav")
+    } else if at(0, b"BM") && bytes.len() > 14 {
+        Some("image/bmp")
+    } else if at(4, b"ftyp") {
+        // ISO base media file: the brand says what it holds
+        match bytes.get(8..12) {
+            Some(b"avif" | b"avis") => Some("image/avif"),
+            Some(b"qt  ") => Some("video/quicktime"),
+            _ => Some("video/mp4"),
+        }
+    } else if at(0, b"\x1a\x45\xdf\xa3") {
+        Some("video/webm")
+    } else if at(0, b"ID3") || at(0, b"\xff\xfb") || at(0, b"\xff\xf3") || at(0, b"\xff\xf2") {
+        Some("audio/mpeg")
+    } else if at(0, b"OggS") {
+        Some("audio/ogg")
+    } else if at(0, b"glTF") {
+        Some("model/gltf-binary")
+    } else {
+        sniff_text(bytes)
+    }
+}
+
+// SVG, HTML and JSON, recognized by their first markup (skipping a BOM, whitespace, XML
+// declarations and comments).
+fn sniff_text(bytes: &[u8]) -> Option<&'static str> {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]).to_ascii_lowercase();
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+    if head.starts_with('{') || head.starts_with('[') {
+        return Some("application/json");
+    }
+    if !head.starts_with('<') {
+        return None;
+    }
+    if head.starts_with("<!doctype html") || head.starts_with("<html") {
+        Some("text/html")
+    } else if head.contains("<svg") {
+        Some("image/svg+xml")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn magic_bytes_win_over_declared_type() {
+        assert_eq!(detect(b"\x89PNG\r\n\x1a\n....", Some("application/octet-stream")), "image/png");
+        assert_eq!(detect(b"\xff\xd8\xff\xe0..JFIF", Some("image/png")), "image/jpeg");
+        assert_eq!(detect(b"RIFF\x00\x00\x00\x00WEBPVP8 ", None), "image/webp");
+        assert_eq!(detect(b"\x00\x00\x00\x18ftypmp42", None), "video/mp4");
+    }
+
+    #[test]
+    fn recognizes_svg_with_prolog() {
+        let svg = b"\xef\xbb\xbf<?xml version=\"1.0\"?>\n<!-- generated -->\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>";
+        assert_eq!(detect(svg, Some("text/plain")), "image/svg+xml");
+        assert_eq!(extension("image/svg+xml"), "svg");
+    }
+
+    #[test]
+    fn falls_back_to_declared_type() {
+        assert_eq!(detect(b"\x00\x01\x02", Some("model/gltf+json")), "model/gltf+json");
+        assert_eq!(detect(b"\x00\x01\x02", Some("binary/octet-stream")), OCTET_STREAM);
+        assert_eq!(extension(&detect(b"\x00\x01\x02", None)), "bin");
+    }
+
+    #[test]
+    fn markup_is_served_as_attachment() {
+        assert!(is_served_as_attachment("text/html"));
+        assert!(is_served_as_attachment("image/svg+xml"));
+        assert!(is_served_as_attachment("application/xhtml+xml"));
+        assert!(!is_served_as_attachment("image/png"));
+        assert!(!is_served_as_attachment("video/mp4"));
+    }
+}
@@ -0,0 +1,44 @@
+// Offset bookkeeping for concurrently processed messages.
+//
+// Jobs finish out of order, but a partition's committed offset says "everything before
+// this is done". The tracker therefore only advances a partition past offsets whose
+// messages, and all messages before them, have finished.
+
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Default)]
+struct PartitionOffsets {
+    in_flight: BTreeSet<i64>,
+    highest_finished: Option<i64>,
+    stored: Option<i64>,
+}
+
+#[derive(Default)]
+pub struct OffsetTracker {
+    partitions: HashMap<(String, i32), PartitionOffsets>,
+}
+
+impl OffsetTracker {
+    pub fn start(&mut self, topic: &str, partition: i32, offset: i64) {
+        let state = self.partitions.entry((topic.to_string(), partition)).or_default();
+        state.in_flight.insert(offset);
+    }
+
+    // Marks a message as finished. Returns the offset of the last message that can now be
+    // stored for commit, if that moved forward.
+    pub fn finish(&mut self, topic: &str, partition: i32, offset: i64) -> Option<i64> {
+        let state = self.partitions.get_mut(&(topic.to_string(), partition))?;
+        state.in_flight.remove(&offset);
+        state.highest_finished = state.highest_finished.max(Some(offset));
+        let done_upto = match state.in_flight.first() {
+            Some(oldest_running) => Some(oldest_running - 1).filter(|&o| o >= 0),
+            None => state.highest_finished,
+        };
+        if done_upto > state.stored {
+            state.stored = done_upto;
+            done_upto
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,81 @@
+// Per-key ordering for concurrently processed messages.
+//
+// The listener keys every job by its contract address. Transfer and refresh jobs with the
+// same key must run in the order they were produced: a transfer out recorded before the
+// transfer in leaves a negative balance behind, and an older refresh would overwrite a
+// newer one. Jobs with different keys run concurrently; a message whose key already has
+// a job running waits in that key's queue until the job finishes.
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+pub struct KeyQueues<K, T> {
+    // A key is present while it has a job running; the queue holds the messages behind it.
+    waiting: HashMap<K, VecDeque<T>>,
+    // Items waiting across all keys
+    queued: usize,
+}
+
+impl<K, T> Default for KeyQueues<K, T> {
+    fn default() -> Self {
+        KeyQueues { waiting: HashMap::new(), queued: 0 }
+    }
+}
+
+impl<K: Hash + Eq, T> KeyQueues<K, T> {
+    // Returns `item` if it can run now, or queues it behind the key's running job.
+    pub fn start(&mut self, key: K, item: T) -> Option<T> {
+        match self.waiting.get_mut(&key) {
+            Some(queue) => {
+                queue.push_back(item);
+                self.queued += 1;
+                None
+            }
+            None => {
+                self.waiting.insert(key, VecDeque::new());
+                Some(item)
+            }
+        }
+    }
+
+    // Marks the key's running job as finished. Returns the next item to run for the key.
+    pub fn finish(&mut self, key: &K) -> Option<T> {
+        let queue = self.waiting.get_mut(key)?;
+        let next = queue.pop_front();
+        match next {
+            Some(_) => self.queued -= 1,
+            None => {
+                self.waiting.remove(key);
+            }
+        }
+        next
+    }
+
+    // Number of items waiting behind a running job.
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_key_runs_in_order() {
+        let mut queues = KeyQueues::default();
+        assert_eq!(queues.start("0xabc", 1), Some(1));
+        assert_eq!(queues.start("0xabc", 2), None);
+        assert_eq!(queues.start("0xdef", 3), Some(3));
+        assert_eq!(queues.start("0xabc", 4), None);
+        assert_eq!(queues.queued(), 2);
+
+        assert_eq!(queues.finish(&"0xabc"), Some(2));
+        assert_eq!(queues.finish(&"0xabc"), Some(4));
+        assert_eq!(queues.finish(&"0xabc"), None);
+        assert_eq!(queues.queued(), 0);
+        // Idle again, so the next message runs right away.
+        assert_eq!(queues.start("0xabc", 5), Some(5));
+        assert_eq!(queues.finish(&"0xdef"), None);
+    }
+}
@@ -0,0 +1,85 @@
+// Resized WebP copies of cached images, so grids can load a thumbnail instead of the
+// full-resolution original.
+
+use image::imageops::FilterType;
+
+pub const MIME_TYPE: &str = "image/webp";
+// Lossy WebP quality (0-100) of every rendition.
+const QUALITY: f32 = 80.0;
+
+pub struct Rendition {
+    pub width: u32,
+    pub height: u32,
+    // None when the original is no wider than this width and serves it as is.
+    pub bytes: Option<Vec<u8>>,
+}
+
+// Raster formats the decoder handles; SVGs scale on their own and are left alone.
+pub fn is_renderable(mime_type: &str) -> bool {
+    matches!(mime_type, "image/png" | "image/jpeg" | "image/gif" | "image/webp" | "image/bmp")
+}
+
+// Decodes an image and encodes a WebP copy at each of `widths` narrower than the original
+// (never upscaling), keeping the aspect ratio. A width whose copy would not be smaller than
+// the original file is left out. CPU-bound: call it from a blocking task.
+pub fn render(bytes: &[u8], widths: &[u32]) -> Result<Vec<Rendition>, image::ImageError> {
+    let original = image::load_from_memory(bytes)?;
+    let mut renditions = Vec::new();
+    for &width in widths {
+        if width >= original.width() {
+            renditions.push(Rendition { width, height: original.height(), bytes: None });
+            continue;
+        }
+        let resized = original.resize(width, u32::MAX, FilterType::CatmullRom).to_rgba8();
+        let encoded = webp::Encoder::from_rgba(resized.as_raw(), resized.width(), resized.height()).encode(QUALITY);
+        if encoded.len() < bytes.len() {
+            renditions.push(Rendition { width, height: resized.height(), bytes: Some(encoded.to_vec()) });
+        }
+    }
+    Ok(renditions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbImage, RgbaImage};
+    use std::io::Cursor;
+
+    #[test]
+    fn renders_narrower_widths_only() {
+        let mut png = Vec::new();
+        RgbaImage::new(600, 300).write_to(&mut Cursor::new(&mut png), ImageFormat::Png).unwrap();
+        let rendered = render(&png, &[256, 1024]).unwrap();
+        assert_eq!(rendered.len(), 2);
+        assert_eq!((rendered[0].width, rendered[0].height), (256, 128));
+        assert_eq!(image::guess_format(rendered[0].bytes.as_ref().unwrap()).unwrap(), ImageFormat::WebP);
+        // Wider than the original: the original is used.
+        assert_eq!((rendered[1].width, rendered[1].height), (1024, 300));
+        assert!(rendered[1].bytes.is_none());
+    }
+
+    #[test]
+    fn photo_renditions_are_smaller_than_the_original() {
+        // Smooth gradients with grain, like a photograph.
+        let photo = RgbImage::from_fn(1200, 800, |x, y| {
+            let grain = ((x * 7919 + y * 104_729) % 23) as u8;
+            image::Rgb([(x / 6) as u8 + grain, (y / 4) as u8 + grain, ((x + y) / 10) as u8 + grain])
+        });
+        let mut jpeg = Vec::new();
+        photo.write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg).unwrap();
+        let rendered = render(&jpeg, &[256, 512]).unwrap();
+        assert_eq!(rendered.len(), 2);
+        for rendition in &rendered {
+            assert!(rendition.bytes.as_ref().unwrap().len() < jpeg.len());
+        }
+    }
+
+    #[test]
+    fn leaves_out_copies_no_smaller_than_the_original() {
+        // A tiny flat GIF is smaller than any WebP container around it.
+        let mut gif = Vec::new();
+        RgbaImage::from_pixel(8, 8, image::Rgba([10, 200, 30, 255])).write_to(&mut Cursor::new(&mut gif), ImageFormat::Gif).unwrap();
+        let rendered = render(&gif, &[4]).unwrap();
+        assert!(rendered.is_empty());
+    }
+}
@@ -0,0 +1,172 @@
+// Retry pipeline for failed jobs.
+//
+// A job that fails is wrapped in a `RetryEnvelope` and published to the retry topic with
+// the attempt count and the time of its next attempt (exponential backoff). The retry
+// consumer waits until that time and runs the job again. After `max_attempts`, or right
+// away for errors that cannot succeed on retry, the job is published to the dead-letter
+// topic and recorded in the `failed_jobs` table.
+
+use db::FailedJob;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    Network,         // request failed or timed out
+    HttpStatus,      // non-200 response
+    InvalidMetadata, // response was not valid metadata JSON
+    MissingUri,      // job has no metadata URI to fetch
+    Storage,         // media upload failed
+    Database,
+    InvalidPayload,  // Kafka message could not be deserialized
+    Panic,           // the job panicked; running it again would panic the same way
+}
+
+impl ErrorClass {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorClass::Network => "network",
+            ErrorClass::HttpStatus => "http_status",
+            ErrorClass::InvalidMetadata => "invalid_metadata",
+            ErrorClass::MissingUri => "missing_uri",
+            ErrorClass::Storage => "storage",
+            ErrorClass::Database => "database",
+            ErrorClass::InvalidPayload => "invalid_payload",
+            ErrorClass::Panic => "panic",
+        }
+    }
+
+    // Whether running the same job again could succeed.
+    pub fn is_retriable(self) -> bool {
+        !matches!(self, ErrorClass::InvalidMetadata | ErrorClass::MissingUri | ErrorClass::InvalidPayload | ErrorClass::Panic)
+    }
+}
+
+#[derive(Debug)]
+pub struct JobError {
+    pub class: ErrorClass,
+    pub message: String,
+}
+
+impl JobError {
+    pub fn new(class: ErrorClass, message: impl Into<String>) -> Self {
+        JobError { class, message: message.into() }
+    }
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.class.as_str())
+    }
+}
+
+impl From<sqlx::Error> for JobError {
+    fn from(e: sqlx::Error) -> Self {
+        JobError::new(ErrorClass::Database, e.to_string())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetryEnvelope {
+    pub original_topic: String,
+    pub key: Option<String>,
+    pub payload: Value,
+    pub attempt: u32, // attempts made so far
+    pub next_attempt_at: u64, // unix milliseconds
+    pub error_class: String,
+    pub last_error: String,
+}
+
+impl RetryEnvelope {
+    // How long until the next attempt is due (zero if it already is).
+    pub fn wait(&self) -> Duration {
+        Duration::from_millis(self.next_attempt_at.saturating_sub(unix_millis()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    // Delay before attempt `attempt + 1`: initial_backoff, doubled per attempt, capped.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+pub struct RetryScheduler {
+    pub producer: FutureProducer,
+    pub pool: PgPool,
+    pub policy: RetryPolicy,
+    pub retry_topic: String,
+    pub dead_letter_topic: String,
+}
+
+impl RetryScheduler {
+    // Records that attempt number `attempt` of a job from `topic` failed with `error`, and
+    // either schedules the next attempt or gives up on the job.
+    pub async fn job_failed(&self, topic: &str, key: Option<&str>, payload: Value, attempt: u32, error: JobError) {
+        if error.class.is_retriable() && attempt < self.policy.max_attempts {
+            let backoff = self.policy.backoff(attempt);
+            let envelope = RetryEnvelope {
+                original_topic: topic.to_string(),
+                key: key.map(str::to_string),
+                payload,
+                attempt,
+                next_attempt_at: unix_millis() + backoff.as_millis() as u64,
+                error_class: error.class.as_str().to_string(),
+                last_error: error.message.clone(),
+            };
+            eprintln!("[RETRY] Attempt {} of job from {} failed ({}), retrying in {:?}", attempt, topic, envelope.last_error, backoff);
+            let body = serde_json::to_string(&envelope).expect("retry envelope serializes");
+            let record = FutureRecord::to(&self.retry_topic).payload(&body).key(key.unwrap_or_default());
+            if let Err((e, _)) = self.producer.send(record, SEND_TIMEOUT).await {
+                eprintln!("[ERROR] Failed to schedule retry: {}", e);
+                self.dead_letter(topic, key, envelope.payload, attempt, error).await;
+            }
+        } else {
+            self.dead_letter(topic, key, payload, attempt, error).await;
+        }
+    }
+
+    async fn dead_letter(&self, topic: &str, key: Option<&str>, payload: Value, attempts: u32, error: JobError) {
+        eprintln!("[DEAD LETTER] Giving up on job from {} after {} attempt(s): {}", topic, attempts, error);
+        let body = payload.to_string();
+        let headers = OwnedHeaders::new()
+            .insert(Header { key: "original_topic", value: Some(topic) })
+            .insert(Header { key: "error_class", value: Some(error.class.as_str()) })
+            .insert(Header { key: "error", value: Some(error.message.as_str()) });
+        let record = FutureRecord::to(&self.dead_letter_topic).payload(&body).key(key.unwrap_or_default()).headers(headers);
+        if let Err((e, _)) = self.producer.send(record, SEND_TIMEOUT).await {
+            eprintln!("[ERROR] Failed to publish to dead-letter topic {}: {}", self.dead_letter_topic, e);
+        }
+        let failed = FailedJob {
+            topic: topic.to_string(),
+            job_key: key.map(str::to_string),
+            payload,
+            attempts: attempts as i32,
+            error_class: error.class.as_str().to_string(),
+            last_error: error.message,
+        };
+        if let Err(e) = db::insert_failed_job(&self.pool, &failed).await {
+            // Nothing else to fall back to: print the job so it can be replayed by hand.
+            eprintln!("[ERROR] Failed to record failed job: {}; dropped job: {}", e, body);
+        }
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
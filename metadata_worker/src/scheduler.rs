@@ -0,0 +1,274 @@
+// Runs consumed messages concurrently.
+//
+// Up to `max_in_flight` handlers run at once, each holding a slot while it works. Messages of the ordered topics run one
+// after another per key, in order; the others, such as mints, run as soon as a slot is
+// free. A message waiting behind its key holds no slot, so one busy key never blocks the
+// rest; at most `max_in_flight` messages wait that way, and reading stops while they do, so
+// a hot key cannot pull the rest of its partition into memory. A partition's offset is committed only once every earlier message of it has
+// finished, so a crash replays unfinished jobs instead of losing them.
+
+use rdkafka::error::KafkaError;
+use rdkafka::message::{Message, OwnedMessage};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::{self, JoinSet};
+use tokio_stream::{Stream, StreamExt};
+
+use crate::offsets::OffsetTracker;
+use crate::ordering::KeyQueues;
+
+// Runs the handler for every message of `messages` until the stream ends. `commit` is
+// called with a partition's new committable offset.
+pub async fn run<S, F, Fut, C>(mut messages: S, max_in_flight: usize, ordered_topics: &[String], handle: F, mut commit: C)
+where
+    S: Stream<Item = Result<OwnedMessage, KafkaError>> + Unpin,
+    F: Fn(OwnedMessage, OwnedSemaphorePermit) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    C: FnMut(&str, i32, i64),
+{
+    let slots = Arc::new(Semaphore::new(max_in_flight));
+    let mut slot = None;
+    let mut offsets = OffsetTracker::default();
+    let mut key_queues = KeyQueues::default();
+    // Messages whose key came free, waiting for a slot; they run before new messages.
+    let mut ready = VecDeque::new();
+    let mut jobs = JoinSet::new();
+    let mut running = HashMap::new();
+    loop {
+        if slot.is_some() {
+            if let Some(m) = ready.pop_front() {
+                let slot = slot.take().expect("a slot is held");
+                spawn_job(&mut jobs, &mut running, &handle, m, slot, ordered_topics);
+                continue;
+            }
+        }
+        tokio::select! {
+            Some(finished) = jobs.join_next_with_id(), if !jobs.is_empty() => {
+                let id = match finished {
+                    Ok((id, ())) => id,
+                    Err(e) => {
+                        // The job itself was dead-lettered by its handler; only the handler failed here.
+                        eprintln!("[ERROR] Job task failed: {e}");
+                        e.id()
+                    }
+                };
+                let Some(RunningJob { topic, partition, offset, key }) = running.remove(&id) else {
+                    continue;
+                };
+                if let Some(done_upto) = offsets.finish(&topic, partition, offset) {
+                    commit(&topic, partition, done_upto);
+                }
+                if let Some(next) = key.and_then(|key| key_queues.finish(&key)) {
+                    ready.push_back(next);
+                }
+            }
+            acquired = slots.clone().acquire_owned(), if slot.is_none() => {
+                slot = Some(acquired.expect("job slots are never closed"));
+            }
+            message = messages.next(), if slot.is_some() && ready.is_empty() && key_queues.queued() < max_in_flight => {
+                match message {
+                    Some(Ok(m)) => {
+                        offsets.start(m.topic(), m.partition(), m.offset());
+                        let runnable = match ordering_key(&m, ordered_topics) {
+                            Some(key) => key_queues.start(key, m),
+                            None => Some(m),
+                        };
+                        // A queued message leaves the slot for the next one.
+                        if let Some(m) = runnable {
+                            let slot = slot.take().expect("a slot is held before reading");
+                            spawn_job(&mut jobs, &mut running, &handle, m, slot, ordered_topics);
+                        }
+                    }
+                    Some(Err(e)) => eprintln!("[ERROR] Kafka error: {e}"),
+                    None => break,
+                }
+            }
+        }
+    }
+}
+
+// Passes the messages of `messages` on to `out` once `due` says they are, in partition
+// order. A partition whose next message is not due yet is paused (`pause(topic, partition,
+// true)`) until it is, so nothing more is read from it meanwhile: at most one held message
+// per partition, plus any that were already fetched. Reading never waits for a message to
+// come due, so the consumer keeps polling and its offsets keep moving.
+pub async fn hold_until_due<S, D, P>(mut messages: S, due: D, mut pause: P, out: mpsc::Sender<Result<OwnedMessage, KafkaError>>)
+where
+    S: Stream<Item = Result<OwnedMessage, KafkaError>> + Unpin,
+    D: Fn(&OwnedMessage) -> Duration,
+    P: FnMut(&str, i32, bool),
+{
+    let mut held: HashMap<(String, i32), VecDeque<OwnedMessage>> = HashMap::new();
+    loop {
+        // Releases every message that came due, and resumes partitions left with none.
+        let mut resumed = Vec::new();
+        for ((topic, partition), queue) in held.iter_mut() {
+            while queue.front().is_some_and(|m| due(m).is_zero()) {
+                let m = queue.pop_front().expect("front was checked");
+                if out.send(Ok(m)).await.is_err() {
+                    return;
+                }
+            }
+            if queue.is_empty() {
+                pause(topic, *partition, false);
+                resumed.push((topic.clone(), *partition));
+            }
+        }
+        for partition in resumed {
+            held.remove(&partition);
+        }
+        let next_due = held.values().filter_map(|queue| queue.front()).map(&due).min();
+
+        tokio::select! {
+            message = messages.next() => match message {
+                Some(Ok(m)) => {
+                    let partition = (m.topic().to_string(), m.partition());
+                    if let Some(queue) = held.get_mut(&partition) {
+                        queue.push_back(m);
+                    } else if !due(&m).is_zero() {
+                        pause(&partition.0, partition.1, true);
+                        held.insert(partition, VecDeque::from([m]));
+                    } else if out.send(Ok(m)).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Err(e)) => {
+                    if out.send(Err(e)).await.is_err() {
+                        return;
+                    }
+                }
+                None => return,
+            },
+            _ = tokio::time::sleep(next_due.unwrap_or_default()), if next_due.is_some() => {}
+        }
+    }
+}
+
+// A message being handled, as `run` needs it once the handler is done.
+struct RunningJob {
+    topic: String,
+    partition: i32,
+    offset: i64,
+    key: Option<(String, Vec<u8>)>,
+}
+
+// Messages of the ordered topics are ordered per topic and key; messages without a key are not.
+fn ordering_key(m: &OwnedMessage, ordered_topics: &[String]) -> Option<(String, Vec<u8>)> {
+    if !ordered_topics.iter().any(|topic| topic == m.topic()) {
+        return None;
+    }
+    m.key().map(|key| (m.topic().to_string(), key.to_vec()))
+}
+
+fn spawn_job<F, Fut>(
+    jobs: &mut JoinSet<()>,
+    running: &mut HashMap<task::Id, RunningJob>,
+    handle: &F,
+    m: OwnedMessage,
+    slot: OwnedSemaphorePermit,
+    ordered_topics: &[String],
+) where
+    F: Fn(OwnedMessage, OwnedSemaphorePermit) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let job = RunningJob {
+        topic: m.topic().to_string(),
+        partition: m.partition(),
+        offset: m.offset(),
+        key: ordering_key(&m, ordered_topics),
+    };
+    let id = jobs.spawn(handle(m, slot)).id();
+    running.insert(id, job);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::message::Timestamp;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::sync::{mpsc, Barrier};
+
+    fn message(topic: &str, key: &str, offset: i64) -> Result<OwnedMessage, KafkaError> {
+        Ok(OwnedMessage::new(Some(b"{}".to_vec()), Some(key.as_bytes().to_vec()), topic.to_string(), Timestamp::NotAvailable, 0, offset, None))
+    }
+
+    // Runs two messages with the same key through `run`; returns whether both handlers
+    // were running at the same time.
+    async fn overlap(topic: &str, ordered_topics: &[String]) -> bool {
+        let messages = tokio_stream::iter(vec![message(topic, "0xabc", 0), message(topic, "0xabc", 1)]).chain(tokio_stream::pending());
+        let barrier = Arc::new(Barrier::new(2));
+        let (started, mut both_started) = mpsc::unbounded_channel();
+        let handle = |_m: OwnedMessage, _slot: OwnedSemaphorePermit| {
+            let barrier = barrier.clone();
+            let started = started.clone();
+            async move {
+                barrier.wait().await;
+                started.send(()).expect("test is listening");
+            }
+        };
+        tokio::select! {
+            _ = run(messages, 4, ordered_topics, handle, |_, _, _| {}) => unreachable!("the stream never ends"),
+            overlapped = tokio::time::timeout(Duration::from_secs(1), async {
+                both_started.recv().await;
+                both_started.recv().await;
+            }) => overlapped.is_ok(),
+        }
+    }
+
+    #[tokio::test]
+    async fn hot_key_stops_reading_once_the_queue_is_full() {
+        let read = Arc::new(AtomicUsize::new(0));
+        let counted = read.clone();
+        let messages = tokio_stream::iter((0..100).map(|offset| message("nft_transfer_jobs", "0xabc", offset)))
+            .map(move |m| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                m
+            });
+        let ordered = ["nft_transfer_jobs".to_string()];
+        // The first job never finishes, so every later one queues behind it.
+        let handle = |_m: OwnedMessage, _slot: OwnedSemaphorePermit| std::future::pending::<()>();
+        let run = run(messages, 2, &ordered, handle, |_, _, _| {});
+        assert!(tokio::time::timeout(Duration::from_millis(200), run).await.is_err());
+        // One running, two queued.
+        assert_eq!(read.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn holds_a_partition_until_its_head_is_due() {
+        let start = tokio::time::Instant::now();
+        // Offset 0 of partition 0 is due in 100ms; everything else already is.
+        let due = move |m: &OwnedMessage| match (m.partition(), m.offset()) {
+            (0, 0) => (start + Duration::from_millis(100)).saturating_duration_since(tokio::time::Instant::now()),
+            _ => Duration::ZERO,
+        };
+        let on_partition = |partition: i32, offset: i64| {
+            Ok(OwnedMessage::new(None, None, "nft_retry_jobs".to_string(), Timestamp::NotAvailable, partition, offset, None))
+        };
+        let messages = tokio_stream::iter(vec![on_partition(0, 0), on_partition(0, 1), on_partition(1, 0)]).chain(tokio_stream::pending());
+        let (out, mut released) = mpsc::channel(8);
+        let (paused, mut pauses) = mpsc::unbounded_channel();
+        let pause = move |_: &str, partition: i32, pause: bool| paused.send((partition, pause)).expect("test is listening");
+        tokio::spawn(hold_until_due(messages, due, pause, out));
+
+        let mut order = Vec::new();
+        for _ in 0..3 {
+            let m = released.recv().await.unwrap().unwrap();
+            order.push((m.partition(), m.offset()));
+        }
+        assert_eq!(order, [(1, 0), (0, 0), (0, 1)]);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(pauses.recv().await, Some((0, true)));
+        assert_eq!(pauses.recv().await, Some((0, false)));
+    }
+
+    #[tokio::test]
+    async fn same_contract_mints_run_concurrently() {
+        let ordered = ["nft_transfer_jobs".to_string()];
+        assert!(overlap("nft_mint_jobs", &ordered).await);
+        assert!(!overlap("nft_transfer_jobs", &ordered).await);
+    }
+}
@@ -0,0 +1,194 @@
+// Where cached media is stored.
+//
+// MEDIA_STORE selects the backend:
+//   - "local": files under MEDIA_LOCAL_DIR (default ./media_cache), for development and
+//     self-hosting; MEDIA_PUBLIC_URL is the base URL the directory is served from
+//   - "s3": an AWS S3 bucket (S3_BUCKET, AWS_REGION)
+//   - "s3_compatible": an S3 API at S3_ENDPOINT, e.g. MinIO; objects are addressed by path
+// It defaults to "s3" when S3_BUCKET is set and to "local" otherwise.
+// MEDIA_PUBLIC_URL overrides the URL prefix of stored objects for every backend (a CDN).
+//
+// Media is untrusted content. S3 objects that could run scripts (HTML, SVG) are stored
+// with Content-Disposition: attachment; whatever serves the local directory has to do the
+// same for .html and .svg files. Either way, serve media from its own cookieless domain,
+// never the API's or the frontend's.
+
+use async_trait::async_trait;
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_s3::config::Credentials;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use std::env;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::mime;
+
+// Objects are keyed by content hash, so an object never changes once written.
+const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+#[async_trait]
+pub trait MediaStore: Send + Sync {
+    // Recorded as nft_media.storage_backend.
+    fn backend(&self) -> &'static str;
+
+    // Public URL of the object stored under `key`.
+    fn url(&self, key: &str) -> String;
+
+    async fn exists(&self, key: &str) -> anyhow::Result<bool>;
+
+    // Stores `bytes` under `key`, replacing any existing object.
+    async fn put(&self, key: &str, bytes: &[u8], content_type: &str) -> anyhow::Result<()>;
+}
+
+// Counts the temporary files LocalStore has written.
+static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+pub struct LocalStore {
+    root: PathBuf,
+    public_url: String,
+}
+
+#[async_trait]
+impl MediaStore for LocalStore {
+    fn backend(&self) -> &'static str {
+        "local"
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url, key)
+    }
+
+    async fn exists(&self, key: &str) -> anyhow::Result<bool> {
+        Ok(tokio::fs::try_exists(self.root.join(key)).await?)
+    }
+
+    // Whatever serves the directory picks the type from the key's extension. The file is
+    // written under a temporary name and renamed into place, so a crash mid-write never
+    // leaves a truncated file that `exists` would then report as cached.
+    async fn put(&self, key: &str, bytes: &[u8], _content_type: &str) -> anyhow::Result<()> {
+        // Unique per write, so concurrent puts of the same key never share a temporary file.
+        let temp_path = self.root.join(format!(".{}.{}.{}.tmp", key, std::process::id(), TEMP_FILES.fetch_add(1, Ordering::Relaxed)));
+        let written = match tokio::fs::write(&temp_path, bytes).await {
+            Ok(()) => tokio::fs::rename(&temp_path, self.root.join(key)).await,
+            Err(e) => Err(e),
+        };
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+        }
+        Ok(written?)
+    }
+}
+
+pub struct S3Store {
+    backend: &'static str,
+    client: S3Client,
+    bucket: String,
+    public_url: String,
+}
+
+#[async_trait]
+impl MediaStore for S3Store {
+    fn backend(&self) -> &'static str {
+        self.backend
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.public_url, key)
+    }
+
+    async fn exists(&self, key: &str) -> anyhow::Result<bool> {
+        match self.client.head_object().bucket(&self.bucket).key(key).send().await {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn put(&self, key: &str, bytes: &[u8], content_type: &str) -> anyhow::Result<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .set_content_disposition(mime::is_served_as_attachment(content_type).then(|| "attachment".to_string()))
+            .cache_control(CACHE_CONTROL)
+            .body(ByteStream::from(bytes.to_vec()))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+pub async fn from_env() -> anyhow::Result<Box<dyn MediaStore>> {
+    let bucket = env::var("S3_BUCKET").ok();
+    let public_url = env::var("MEDIA_PUBLIC_URL").ok().map(|url| url.trim_end_matches('/').to_string());
+    let backend = env::var("MEDIA_STORE").unwrap_or_else(|_| if bucket.is_some() { "s3" } else { "local" }.to_string());
+    match backend.as_str() {
+        "local" => {
+            let root = PathBuf::from(env::var("MEDIA_LOCAL_DIR").unwrap_or_else(|_| "./media_cache".to_string()));
+            tokio::fs::create_dir_all(&root).await?;
+            let public_url = match public_url {
+                Some(url) => url,
+                None => format!("file://{}", root.canonicalize()?.display()),
+            };
+            Ok(Box::new(LocalStore { root, public_url }))
+        }
+        "s3" => {
+            let bucket = bucket.ok_or_else(|| anyhow::anyhow!("S3_BUCKET must be set for MEDIA_STORE=s3"))?;
+            let region = env::var("AWS_REGION").map_err(|_| anyhow::anyhow!("AWS_REGION must be set for MEDIA_STORE=s3"))?;
+            let client = S3Client::new(&sdk_config(&region).await);
+            let public_url = public_url.unwrap_or_else(|| format!("https://{}.s3.{}.amazonaws.com", bucket, region));
+            Ok(Box::new(S3Store { backend: "s3", client, bucket, public_url }))
+        }
+        "s3_compatible" => {
+            let bucket = bucket.ok_or_else(|| anyhow::anyhow!("S3_BUCKET must be set for MEDIA_STORE=s3_compatible"))?;
+            let endpoint = env::var("S3_ENDPOINT").map_err(|_| anyhow::anyhow!("S3_ENDPOINT must be set for MEDIA_STORE=s3_compatible"))?;
+            let endpoint = endpoint.trim_end_matches('/').to_string();
+            // MinIO ignores the region, but the SDK needs one to sign requests
+            let region = env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+            let config = aws_sdk_s3::config::Builder::from(&sdk_config(&region).await)
+                .endpoint_url(&endpoint)
+                .force_path_style(true)
+                .build();
+            let client = S3Client::from_conf(config);
+            let public_url = public_url.unwrap_or_else(|| format!("{}/{}", endpoint, bucket));
+            Ok(Box::new(S3Store { backend: "s3_compatible", client, bucket, public_url }))
+        }
+        other => anyhow::bail!("Unknown MEDIA_STORE '{}' (expected local, s3 or s3_compatible)", other),
+    }
+}
+
+// Uses AWS_ACCESS_KEY_ID / AWS_SECRET_ACCESS_KEY when set, the default credential chain
+// (instance profile, ~/.aws) otherwise.
+async fn sdk_config(region: &str) -> aws_config::SdkConfig {
+    let mut loader = aws_config::defaults(BehaviorVersion::latest()).region(Region::new(region.to_string()));
+    if let (Ok(access_key), Ok(secret_key)) = (env::var("AWS_ACCESS_KEY_ID"), env::var("AWS_SECRET_ACCESS_KEY")) {
+        loader = loader.credentials_provider(Credentials::new(access_key, secret_key, None, None, "env"));
+    }
+    loader.load().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_put_leaves_only_the_stored_file() {
+        let root = env::temp_dir().join(format!("media_store_test_{}", std::process::id()));
+        tokio::fs::create_dir_all(&root).await.unwrap();
+        let store = LocalStore { root: root.clone(), public_url: "http://media.test".to_string() };
+
+        store.put("abc.png", b"first", "image/png").await.unwrap();
+        store.put("abc.png", b"second", "image/png").await.unwrap();
+
+        assert_eq!(tokio::fs::read(root.join("abc.png")).await.unwrap(), b"second");
+        let mut entries = tokio::fs::read_dir(&root).await.unwrap();
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            names.push(entry.file_name());
+        }
+        assert_eq!(names, ["abc.png"]);
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+}
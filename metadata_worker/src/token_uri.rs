@@ -0,0 +1,76 @@
+// ERC-1155 metadata URI templates.
+//
+// `uri(id)` may return one URI for the whole contract with an `{id}` placeholder, which
+// EIP-1155 says clients replace with the token id as 64 lowercase hex digits, no 0x
+// (https://x/{id}.json -> https://x/000...004d.json). Some contracts serve decimal ids
+// instead, so that form is the fallback.
+
+const ID_PLACEHOLDER: &str = "{id}";
+
+// The URIs to try for a token, in order. URIs without a placeholder are returned as is.
+pub fn candidates(uri: &str, token_id: &str) -> Vec<String> {
+    if !uri.contains(ID_PLACEHOLDER) {
+        return vec![uri.to_string()];
+    }
+    let decimal = uri.replace(ID_PLACEHOLDER, token_id);
+    match hex_id(token_id) {
+        Some(hex) => vec![uri.replace(ID_PLACEHOLDER, &hex), decimal],
+        None => vec![decimal],
+    }
+}
+
+// A decimal uint256 as 64 lowercase hex digits, or None if it is not one.
+fn hex_id(token_id: &str) -> Option<String> {
+    if token_id.is_empty() {
+        return None;
+    }
+    // Big-endian 32-bit limbs; multiply by 10 and add each digit.
+    let mut limbs = [0u32; 8];
+    for digit in token_id.chars() {
+        let mut carry = u64::from(digit.to_digit(10)?);
+        for limb in limbs.iter_mut().rev() {
+            let value = u64::from(*limb) * 10 + carry;
+            *limb = value as u32;
+            carry = value >> 32;
+        }
+        if carry != 0 {
+            return None; // larger than uint256
+        }
+    }
+    Some(limbs.iter().map(|limb| format!("{:08x}", limb)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitutes_padded_hex_then_decimal() {
+        assert_eq!(
+            candidates("https://x/{id}.json", "77"),
+            vec![
+                "https://x/000000000000000000000000000000000000000000000000000000000000004d.json".to_string(),
+                "https://x/77.json".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn pads_large_ids() {
+        let max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        assert_eq!(hex_id(max), Some("f".repeat(64)));
+        assert_eq!(hex_id("4294967296"), Some(format!("{:064x}", 1u64 << 32)));
+        // 2^256 does not fit
+        assert_eq!(hex_id("115792089237316195423570985008687907853269984665640564039457584007913129639936"), None);
+    }
+
+    #[test]
+    fn falls_back_to_decimal_for_unparseable_ids() {
+        assert_eq!(candidates("ipfs://cid/{id}", "0x1f"), vec!["ipfs://cid/0x1f".to_string()]);
+    }
+
+    #[test]
+    fn leaves_uris_without_placeholder_alone() {
+        assert_eq!(candidates("https://x/77.json", "77"), vec!["https://x/77.json".to_string()]);
+    }
+}